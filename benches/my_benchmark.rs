@@ -1,21 +1,24 @@
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
-use umlsm::state::InitialPseudostate;
+use std::any::Any;
+use umlsm::state::InitialPseudoState;
 use umlsm::state::SimpleVertex;
 use umlsm::transition::ftrans;
-use umlsm::StateMachine;
+use umlsm::{EnterSmEvent, SmBuilder};
 
-struct MyState;
+#[allow(dead_code)]
+struct MyState(i32);
 
 #[allow(unused_must_use)]
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("test", |b| {
         b.iter_batched(
             || {
-                StateMachine::new()
-                    .register_vertex(SimpleVertex::<MyState>::new().boxed())
-                    .transition(ftrans(|_: InitialPseudostate, event: i32| {
-                        (MyState, event * 2)
-                    }))
+                SmBuilder::<dyn Any>::new()
+                    .register_vertex(SimpleVertex::<MyState>::new().to_vertex())
+                    .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| MyState(0)))
+                    .transition(ftrans(|_: MyState, event: i32| MyState(event * 2)))
+                    .build()
+                    .unwrap()
             },
             |mut s| {
                 s.process(black_box(3));
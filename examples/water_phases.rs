@@ -1,9 +1,7 @@
 // https://www.uml-diagrams.org/examples/water-phase-uml-state-machine-diagram-example.html
 
-use std::any::{Any, TypeId};
 use std::fmt::Debug;
-use umlsm::state::{Cast, InitialPseudoState, SimpleVertex};
-use umlsm::transition::{ftrans, Switch, Transition};
+use umlsm::state::{InitialPseudoState, SimpleVertex};
 use umlsm::{events, states, switch, EnterSmEvent, Event, SmBuilder};
 
 events! {
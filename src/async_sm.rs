@@ -0,0 +1,961 @@
+//! Asynchronous state machine.
+//!
+//! `AsyncSm` is the counterpart of `Sm`, whose guards, transitions, entry and exit actions are
+//! asynchronous. Processing of an event awaits them in the UML order: guards of the transition,
+//! exit action of the source state, the transition itself and entry action of the target state.
+//!
+//! The state machine does not depend on any runtime, its futures can be polled by any executor.
+//! The futures are not `Send`, so the state machine is driven from a single task, e.g. inside
+//! tokio's `LocalSet`.
+//!
+//! Only flat state machines are supported: simple and final states, initial, terminate, entry and
+//! exit pseudo-states. Composite states, history, junctions and deferred events are not.
+
+use crate::event::{EnterSmEvent, Event};
+use crate::guard::{CtxGuard, NamedGuard};
+use crate::sm::{TransitionInfo, Validate, VertexInfo};
+use crate::state::{Cast, InitialPseudoState, TerminatePseudoState};
+use crate::transition::TypeInfo;
+use crate::{BuildError, SmError};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::fmt::{Debug, Formatter};
+use core::future::{ready, Future};
+use core::marker::PhantomData;
+use core::ops::AsyncFn;
+use core::pin::Pin;
+
+/// Future returned by asynchronous guards, actions and transitions.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Asynchronous condition of the transition, see `Guard`.
+pub trait AsyncGuard<Event, Ctx = ()> {
+    fn check<'a>(&'a self, input: &'a Event, ctx: &'a mut Ctx) -> BoxFuture<'a, bool>;
+    /// Name of the guard, see `Guard::name`.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl<F, Event, Ctx> AsyncGuard<Event, Ctx> for F
+where
+    F: AsyncFn(&Event) -> bool,
+{
+    fn check<'a>(&'a self, input: &'a Event, _: &'a mut Ctx) -> BoxFuture<'a, bool> {
+        Box::pin(self(input))
+    }
+}
+
+impl<F, Event, Ctx> AsyncGuard<Event, Ctx> for CtxGuard<F>
+where
+    F: AsyncFn(&Event, &mut Ctx) -> bool,
+{
+    fn check<'a>(&'a self, input: &'a Event, ctx: &'a mut Ctx) -> BoxFuture<'a, bool> {
+        Box::pin((self.0)(input, ctx))
+    }
+}
+
+impl<G, Event, Ctx> AsyncGuard<Event, Ctx> for NamedGuard<G>
+where
+    G: AsyncGuard<Event, Ctx>,
+{
+    fn check<'a>(&'a self, input: &'a Event, ctx: &'a mut Ctx) -> BoxFuture<'a, bool> {
+        self.guard.check(input, ctx)
+    }
+    fn name(&self) -> Option<&str> {
+        Some(self.name)
+    }
+}
+
+/// Asynchronous action of the entry or exit pseudo-state, see `ActionPoint`.
+pub trait AsyncActionPoint<Ctx = ()> {
+    fn perform_action<'a>(&'a self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()>;
+}
+
+impl<F, Ctx> AsyncActionPoint<Ctx> for F
+where
+    F: AsyncFn(&mut Ctx),
+{
+    fn perform_action<'a>(&'a self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        Box::pin(self(ctx))
+    }
+}
+
+/// Vertex of `AsyncSm` with asynchronous entry and exit actions, see `StateTrait`.
+pub trait AsyncStateTrait<DynData: ?Sized, Ctx = ()> {
+    fn entry<'a>(&'a mut self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()>;
+    fn exit<'a>(&'a mut self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()>;
+    fn get_data(&mut self) -> Box<DynData>;
+    fn get_data_as_ref(&self) -> &DynData;
+    fn set_data(&mut self, data: Box<DynData>);
+    fn data_tid(&self) -> TypeId;
+    fn data_type_name(&self) -> &'static str;
+}
+
+/// Entry or exit action of `AsyncState`.
+trait AsyncHook<T, Ctx> {
+    fn call<'a>(&'a self, data: &'a T, ctx: &'a mut Ctx) -> BoxFuture<'a, ()>;
+}
+
+impl<F, T, Ctx> AsyncHook<T, Ctx> for F
+where
+    F: AsyncFn(&T, &mut Ctx),
+{
+    fn call<'a>(&'a self, data: &'a T, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        Box::pin(self(data, ctx))
+    }
+}
+
+/// State with asynchronous entry and exit actions, see `SimpleVertex`.
+pub struct AsyncState<T, Ctx = ()> {
+    data: Option<Box<T>>,
+    entry: Option<Box<dyn AsyncHook<T, Ctx>>>,
+    exit: Option<Box<dyn AsyncHook<T, Ctx>>>,
+}
+
+impl<T, Ctx> Debug for AsyncState<T, Ctx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncState")
+            .field("data", &core::any::type_name::<T>())
+            .finish()
+    }
+}
+
+impl<T: 'static, Ctx: 'static> Default for AsyncState<T, Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: 'static, Ctx: 'static> AsyncState<T, Ctx> {
+    pub fn new() -> Self {
+        AsyncState {
+            data: None,
+            entry: None,
+            exit: None,
+        }
+    }
+
+    pub fn with_data(data: T) -> Self {
+        AsyncState {
+            data: Some(Box::new(data)),
+            ..Self::new()
+        }
+    }
+
+    /// Sets the action, that is awaited when the state is entered.
+    pub fn with_entry<F>(mut self, entry: F) -> Self
+    where
+        F: AsyncFn(&T, &mut Ctx) + 'static,
+    {
+        self.entry = Some(Box::new(entry));
+        self
+    }
+
+    /// Sets the action, that is awaited when the state is exited.
+    pub fn with_exit<F>(mut self, exit: F) -> Self
+    where
+        F: AsyncFn(&T, &mut Ctx) + 'static,
+    {
+        self.exit = Some(Box::new(exit));
+        self
+    }
+
+    pub fn to_vertex<DynData>(self) -> AsyncVertex<DynData, Ctx>
+    where
+        DynData: Cast<T> + ?Sized,
+    {
+        AsyncVertex::State(Box::new(self))
+    }
+
+    pub fn to_final_vertex<DynData>(self) -> AsyncVertex<DynData, Ctx>
+    where
+        DynData: Cast<T> + ?Sized,
+    {
+        AsyncVertex::FinalState(Box::new(self))
+    }
+}
+
+impl<T, DynData, Ctx> AsyncStateTrait<DynData, Ctx> for AsyncState<T, Ctx>
+where
+    T: 'static,
+    DynData: Cast<T> + ?Sized,
+{
+    fn entry<'a>(&'a mut self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        let data = self.data.as_deref().expect("Entered state must have data");
+        match &self.entry {
+            Some(entry) => entry.call(data, ctx),
+            None => Box::pin(ready(())),
+        }
+    }
+
+    fn exit<'a>(&'a mut self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        let data = self.data.as_deref().expect("Exited state must have data");
+        match &self.exit {
+            Some(exit) => exit.call(data, ctx),
+            None => Box::pin(ready(())),
+        }
+    }
+
+    fn get_data(&mut self) -> Box<DynData> {
+        DynData::upcast(self.data.take().expect("State must have data"))
+    }
+
+    fn get_data_as_ref(&self) -> &DynData {
+        DynData::upcast_ref(self.data.as_deref().expect("State must have data"))
+    }
+
+    fn set_data(&mut self, data: Box<DynData>) {
+        self.data = Some(DynData::downcast(data));
+    }
+
+    fn data_tid(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    fn data_type_name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// Pseudo-state of `AsyncSm`, see `PseudoState`.
+pub struct AsyncPseudoState<DynData: ?Sized, Ctx = ()> {
+    data: Option<Box<DynData>>,
+    data_tid: TypeId,
+    data_type_name: &'static str,
+    pub kind: AsyncPseudoStateKind<Ctx>,
+}
+
+pub enum AsyncPseudoStateKind<Ctx = ()> {
+    Initial,
+    Terminate,
+    /// Contains the action, that is awaited when the pseudo-state is entered.
+    Entry(Box<dyn AsyncActionPoint<Ctx>>),
+    /// Contains the action, that is awaited when the pseudo-state is exited.
+    Exit(Box<dyn AsyncActionPoint<Ctx>>),
+}
+
+impl<DynData: ?Sized, Ctx> AsyncPseudoState<DynData, Ctx> {
+    pub fn new<T: 'static>(data: Option<Box<T>>, kind: AsyncPseudoStateKind<Ctx>) -> Self
+    where
+        DynData: Cast<T>,
+    {
+        AsyncPseudoState {
+            data: data.map(DynData::upcast),
+            data_tid: TypeId::of::<T>(),
+            data_type_name: core::any::type_name::<T>(),
+            kind,
+        }
+    }
+}
+
+impl<DynData: ?Sized, Ctx> AsyncStateTrait<DynData, Ctx> for AsyncPseudoState<DynData, Ctx> {
+    fn entry<'a>(&'a mut self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        match &self.kind {
+            AsyncPseudoStateKind::Entry(action) => action.perform_action(ctx),
+            _ => Box::pin(ready(())),
+        }
+    }
+
+    fn exit<'a>(&'a mut self, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        match &self.kind {
+            AsyncPseudoStateKind::Exit(action) => action.perform_action(ctx),
+            _ => Box::pin(ready(())),
+        }
+    }
+
+    fn get_data(&mut self) -> Box<DynData> {
+        self.data.take().expect("Pseudo-state must have data")
+    }
+
+    fn get_data_as_ref(&self) -> &DynData {
+        self.data.as_deref().expect("Pseudo-state must have data")
+    }
+
+    fn set_data(&mut self, data: Box<DynData>) {
+        self.data = Some(data);
+    }
+
+    fn data_tid(&self) -> TypeId {
+        self.data_tid
+    }
+
+    fn data_type_name(&self) -> &'static str {
+        self.data_type_name
+    }
+}
+
+/// Vertex of `AsyncSm`, see `Vertex`.
+pub enum AsyncVertex<DynData: ?Sized, Ctx = ()> {
+    State(Box<dyn AsyncStateTrait<DynData, Ctx>>),
+    PseudoState(AsyncPseudoState<DynData, Ctx>),
+    FinalState(Box<dyn AsyncStateTrait<DynData, Ctx>>),
+}
+
+impl<DynData: ?Sized, Ctx> Debug for AsyncVertex<DynData, Ctx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let kind = match self {
+            AsyncVertex::State(_) => "State",
+            AsyncVertex::PseudoState(_) => "PseudoState",
+            AsyncVertex::FinalState(_) => "FinalState",
+        };
+        f.debug_tuple(kind).field(&self.data_type_name()).finish()
+    }
+}
+
+impl<DynData: ?Sized, Ctx> AsyncVertex<DynData, Ctx> {
+    pub fn terminate() -> Self
+    where
+        DynData: Cast<TerminatePseudoState>,
+    {
+        AsyncVertex::PseudoState(AsyncPseudoState::new(
+            Some(Box::new(TerminatePseudoState)),
+            AsyncPseudoStateKind::Terminate,
+        ))
+    }
+
+    fn state(&self) -> &dyn AsyncStateTrait<DynData, Ctx> {
+        match self {
+            AsyncVertex::State(state) | AsyncVertex::FinalState(state) => state.as_ref(),
+            AsyncVertex::PseudoState(ps) => ps,
+        }
+    }
+
+    fn state_mut(&mut self) -> &mut dyn AsyncStateTrait<DynData, Ctx> {
+        match self {
+            AsyncVertex::State(state) | AsyncVertex::FinalState(state) => state.as_mut(),
+            AsyncVertex::PseudoState(ps) => ps,
+        }
+    }
+
+    pub fn get_data_as_ref(&self) -> &DynData {
+        self.state().get_data_as_ref()
+    }
+
+    pub fn data_tid(&self) -> TypeId {
+        self.state().data_tid()
+    }
+
+    pub fn data_type_name(&self) -> &'static str {
+        self.state().data_type_name()
+    }
+}
+
+impl<DynData: ?Sized, Ctx> VertexInfo for AsyncVertex<DynData, Ctx> {
+    fn data_type(&self) -> TypeInfo {
+        TypeInfo {
+            tid: self.data_tid(),
+            name: self.data_type_name(),
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        matches!(self, AsyncVertex::FinalState(_))
+    }
+
+    fn is_terminate(&self) -> bool {
+        matches!(
+            self,
+            AsyncVertex::PseudoState(AsyncPseudoState {
+                kind: AsyncPseudoStateKind::Terminate,
+                ..
+            })
+        )
+    }
+
+    fn is_history(&self) -> bool {
+        false
+    }
+
+    fn may_stay(&self) -> bool {
+        self.is_final() || self.is_terminate()
+    }
+}
+
+/// Asynchronous transition of `AsyncSm`, see `Transition`.
+///
+/// The state machine checks the transition first, and only when it is allowed exits the source
+/// state and performs the transition with its data.
+pub trait AsyncTransition<DynData: ?Sized = dyn Any, Ctx = ()> {
+    /// Checks guards of the transition, without taking data of the source state and the event.
+    fn check<'a>(
+        &'a self,
+        state: &'a DynData,
+        event: &'a dyn Any,
+        ctx: &'a mut Ctx,
+    ) -> BoxFuture<'a, bool>;
+    /// Turns data of the exited source state into data of the target state.
+    fn perform<'a>(
+        &'a self,
+        state: Box<DynData>,
+        event: Event,
+        ctx: &'a mut Ctx,
+    ) -> BoxFuture<'a, Box<DynData>>;
+    /// Type of the input vertex data.
+    fn input_type(&self) -> TypeInfo;
+    /// Types of data of all vertexes this transition can end in.
+    fn output_types(&self) -> Vec<TypeInfo>;
+    /// Type of the event that triggers this transition.
+    fn event_type(&self) -> TypeInfo;
+    /// Returns `true` if the transition can be rejected by a guard.
+    fn is_guarded(&self) -> bool {
+        false
+    }
+}
+
+impl<DynData: ?Sized, Ctx> TransitionInfo for dyn AsyncTransition<DynData, Ctx> {
+    fn input_type(&self) -> TypeInfo {
+        AsyncTransition::input_type(self)
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        AsyncTransition::output_types(self)
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        AsyncTransition::event_type(self)
+    }
+}
+
+/// Guard of `AsyncFuncTransition` that takes the event as `dyn Any`.
+trait AnyEventGuard<Ctx> {
+    fn check<'a>(&'a self, event: &'a dyn Any, ctx: &'a mut Ctx) -> BoxFuture<'a, bool>;
+}
+
+struct OfEvent<Event, G>(G, PhantomData<fn(&Event)>);
+
+impl<Event: 'static, G, Ctx> AnyEventGuard<Ctx> for OfEvent<Event, G>
+where
+    G: AsyncGuard<Event, Ctx>,
+{
+    fn check<'a>(&'a self, event: &'a dyn Any, ctx: &'a mut Ctx) -> BoxFuture<'a, bool> {
+        match event.downcast_ref() {
+            Some(event) => self.0.check(event, ctx),
+            None => Box::pin(ready(false)),
+        }
+    }
+}
+
+/// Transition made from the asynchronous function, see `atrans`.
+pub struct AsyncFuncTransition<F, Args, Ctx = ()> {
+    f: F,
+    guards: Vec<Box<dyn AnyEventGuard<Ctx>>>,
+    _args: PhantomData<fn(Args)>,
+}
+
+/// Makes the transition from the asynchronous function, that takes data of the source state and
+/// the event, and optionally the context, and returns data of the target state. See `ftrans`.
+pub fn atrans<F, Args, Ctx>(f: F) -> AsyncFuncTransition<F, Args, Ctx>
+where
+    F: Into<AsyncFuncTransition<F, Args, Ctx>>,
+{
+    f.into()
+}
+
+impl<F, Input, Output, FEvent, Ctx> From<F> for AsyncFuncTransition<F, (Input, FEvent), Ctx>
+where
+    F: AsyncFn(Input, FEvent) -> Output,
+{
+    fn from(f: F) -> Self {
+        AsyncFuncTransition {
+            f,
+            guards: vec![],
+            _args: PhantomData,
+        }
+    }
+}
+
+impl<F, Input, Output, FEvent, Ctx> From<F> for AsyncFuncTransition<F, (Input, FEvent, Ctx), Ctx>
+where
+    F: AsyncFn(Input, FEvent, &mut Ctx) -> Output,
+{
+    fn from(f: F) -> Self {
+        AsyncFuncTransition {
+            f,
+            guards: vec![],
+            _args: PhantomData,
+        }
+    }
+}
+
+impl<F, Args, Ctx> AsyncFuncTransition<F, Args, Ctx> {
+    fn check_guards<'a>(&'a self, event: &'a dyn Any, ctx: &'a mut Ctx) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            for guard in &self.guards {
+                if !guard.check(event, ctx).await {
+                    return false;
+                }
+            }
+            true
+        })
+    }
+}
+
+impl<F, Input, FEvent: 'static, Ctx> AsyncFuncTransition<F, (Input, FEvent), Ctx> {
+    /// Adds the guard, guards are awaited in the order they are added.
+    pub fn guard<G: AsyncGuard<FEvent, Ctx> + 'static>(mut self, guard: G) -> Self {
+        self.guards.push(Box::new(OfEvent(guard, PhantomData)));
+        self
+    }
+}
+
+impl<F, Input, FEvent: 'static, Ctx> AsyncFuncTransition<F, (Input, FEvent, Ctx), Ctx> {
+    /// Adds the guard, guards are awaited in the order they are added.
+    pub fn guard<G: AsyncGuard<FEvent, Ctx> + 'static>(mut self, guard: G) -> Self {
+        self.guards.push(Box::new(OfEvent(guard, PhantomData)));
+        self
+    }
+}
+
+impl<F, Input, Output, FEvent, DynData, Ctx> AsyncTransition<DynData, Ctx>
+    for AsyncFuncTransition<F, (Input, FEvent), Ctx>
+where
+    F: AsyncFn(Input, FEvent) -> Output,
+    Input: 'static,
+    Output: 'static,
+    FEvent: 'static,
+    DynData: Cast<Input> + Cast<Output> + ?Sized,
+{
+    fn check<'a>(
+        &'a self,
+        _: &'a DynData,
+        event: &'a dyn Any,
+        ctx: &'a mut Ctx,
+    ) -> BoxFuture<'a, bool> {
+        self.check_guards(event, ctx)
+    }
+
+    fn perform<'a>(
+        &'a self,
+        state: Box<DynData>,
+        event: Event,
+        _: &'a mut Ctx,
+    ) -> BoxFuture<'a, Box<DynData>> {
+        Box::pin(async move {
+            let input: Box<Input> = state.downcast();
+            let event = event
+                .downcast::<FEvent>()
+                .expect("Event type is checked by AsyncSm");
+            DynData::upcast(Box::new((self.f)(*input, *event).await))
+        })
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Output>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+
+    fn is_guarded(&self) -> bool {
+        !self.guards.is_empty()
+    }
+}
+
+impl<F, Input, Output, FEvent, DynData, Ctx> AsyncTransition<DynData, Ctx>
+    for AsyncFuncTransition<F, (Input, FEvent, Ctx), Ctx>
+where
+    F: AsyncFn(Input, FEvent, &mut Ctx) -> Output,
+    Input: 'static,
+    Output: 'static,
+    FEvent: 'static,
+    DynData: Cast<Input> + Cast<Output> + ?Sized,
+{
+    fn check<'a>(
+        &'a self,
+        _: &'a DynData,
+        event: &'a dyn Any,
+        ctx: &'a mut Ctx,
+    ) -> BoxFuture<'a, bool> {
+        self.check_guards(event, ctx)
+    }
+
+    fn perform<'a>(
+        &'a self,
+        state: Box<DynData>,
+        event: Event,
+        ctx: &'a mut Ctx,
+    ) -> BoxFuture<'a, Box<DynData>> {
+        Box::pin(async move {
+            let input: Box<Input> = state.downcast();
+            let event = event
+                .downcast::<FEvent>()
+                .expect("Event type is checked by AsyncSm");
+            DynData::upcast(Box::new((self.f)(*input, *event, ctx).await))
+        })
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Output>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+
+    fn is_guarded(&self) -> bool {
+        !self.guards.is_empty()
+    }
+}
+
+type AsyncTransitions<DynData, Ctx> = BTreeMap<TypeId, Vec<Box<dyn AsyncTransition<DynData, Ctx>>>>;
+
+/// Builder of `AsyncSm`, see `SmBuilder`.
+pub struct AsyncSmBuilder<DynData: ?Sized = dyn Any, Ctx = ()> {
+    vertexes: Vec<AsyncVertex<DynData, Ctx>>,
+    transitions: AsyncTransitions<DynData, Ctx>,
+}
+
+impl<DynData: ?Sized, Ctx> Debug for AsyncSmBuilder<DynData, Ctx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncSmBuilder").finish()
+    }
+}
+
+impl<DynData, Ctx> Default for AsyncSmBuilder<DynData, Ctx>
+where
+    DynData: Cast<InitialPseudoState> + ?Sized + 'static,
+    Ctx: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<DynData, Ctx> AsyncSmBuilder<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    pub fn new() -> Self
+    where
+        DynData: Cast<InitialPseudoState>,
+    {
+        let vertexes = vec![AsyncVertex::PseudoState(AsyncPseudoState::new(
+            Some(Box::new(InitialPseudoState)),
+            AsyncPseudoStateKind::Initial,
+        ))];
+        AsyncSmBuilder {
+            vertexes,
+            transitions: BTreeMap::new(),
+        }
+    }
+
+    pub fn register_vertex(mut self, vertex: AsyncVertex<DynData, Ctx>) -> Self {
+        self.vertexes.push(vertex);
+        self
+    }
+
+    pub fn transition<T: AsyncTransition<DynData, Ctx> + 'static>(mut self, transition: T) -> Self {
+        self.transitions
+            .entry(transition.input_type().tid)
+            .or_default()
+            .push(Box::new(transition));
+        self
+    }
+
+    /// Builds the state machine and awaits the initial transition, see `SmBuilder::build`.
+    pub async fn build(self) -> Result<AsyncSm<DynData, Ctx>, Vec<BuildError>>
+    where
+        Ctx: Default,
+    {
+        self.build_with_ctx(Ctx::default()).await
+    }
+
+    /// Builds the state machine with the context, see `build`.
+    pub async fn build_with_ctx(self, ctx: Ctx) -> Result<AsyncSm<DynData, Ctx>, Vec<BuildError>> {
+        self.validate()?;
+        let mut sm = AsyncSm {
+            state: 0,
+            vertexes: self.vertexes,
+            transitions: self.transitions,
+            terminated: false,
+            ctx,
+        };
+        sm.process(EnterSmEvent)
+            .await
+            .map_err(|e| vec![BuildError::Init(e)])?;
+        Ok(sm)
+    }
+
+    /// Checks the state machine the same way as `SmBuilder::validate`.
+    pub fn validate(&self) -> Result<(), Vec<BuildError>> {
+        let errors = self.errors();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+impl<DynData: ?Sized, Ctx> Validate for AsyncSmBuilder<DynData, Ctx> {
+    type Vertex = AsyncVertex<DynData, Ctx>;
+    type Transition = dyn AsyncTransition<DynData, Ctx>;
+
+    fn vertexes(&self) -> &[Self::Vertex] {
+        &self.vertexes
+    }
+
+    fn transitions(&self) -> &AsyncTransitions<DynData, Ctx> {
+        &self.transitions
+    }
+}
+
+/// Asynchronous state machine, see the module documentation. `Ctx` is the context of the state
+/// machine, see `Sm`.
+pub struct AsyncSm<DynData: ?Sized = dyn Any, Ctx = ()> {
+    state: usize,
+    vertexes: Vec<AsyncVertex<DynData, Ctx>>,
+    transitions: AsyncTransitions<DynData, Ctx>,
+    terminated: bool,
+    ctx: Ctx,
+}
+
+impl<DynData: ?Sized, Ctx> Debug for AsyncSm<DynData, Ctx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncSm")
+            .field("state", &self.vertexes[self.state])
+            .field("terminated", &self.terminated)
+            .finish()
+    }
+}
+
+impl<DynData, Ctx> AsyncSm<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    pub async fn process<E: Any>(&mut self, event: E) -> Result<(), SmError<E>> {
+        self.process_boxed(Box::new(event))
+            .await
+            .map_err(|e| e.map(|e| *e.downcast().unwrap()))
+    }
+
+    /// Processes the event. Guards of transitions from the current state are awaited in the order
+    /// the transitions are registered, and the first allowed transition is performed: exit action
+    /// of the current state is awaited, then the transition, then entry action of the target
+    /// state.
+    pub async fn process_boxed(&mut self, event: Event) -> Result<(), SmError<Event>> {
+        if self.terminated {
+            return Err(SmError::Terminated(event));
+        }
+        let source = &mut self.vertexes[self.state];
+        let transitions = match self.transitions.get(&source.data_tid()) {
+            Some(transitions) => transitions,
+            None => return Err(SmError::NoTransitionsFromThisVertex(event)),
+        };
+        let event_tid = event.as_ref().type_id();
+        let mut selected = None;
+        for transition in transitions
+            .iter()
+            .filter(|t| t.event_type().tid == event_tid)
+        {
+            if transition
+                .check(source.get_data_as_ref(), event.as_ref(), &mut self.ctx)
+                .await
+            {
+                selected = Some(transition);
+                break;
+            }
+        }
+        let transition = match selected {
+            Some(transition) => transition,
+            None => return Err(SmError::NoTransitionSatisfyingEvent(event)),
+        };
+
+        source.state_mut().exit(&mut self.ctx).await;
+        let data = source.state_mut().get_data();
+        let data = transition.perform(data, event, &mut self.ctx).await;
+        let target = self
+            .vertexes
+            .iter()
+            .position(|v| v.data_tid() == data.as_ref().type_id())
+            .expect("Target vertex is checked by AsyncSmBuilder");
+        let vertex = self.vertexes[target].state_mut();
+        vertex.set_data(data);
+        vertex.entry(&mut self.ctx).await;
+        self.state = target;
+        self.terminated = self.vertexes[target].is_terminate();
+        Ok(())
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// Returns `true` if the state machine is in the final state.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.vertexes[self.state], AsyncVertex::FinalState(_))
+    }
+
+    pub fn current_state(&self) -> &DynData {
+        self.vertexes[self.state].get_data_as_ref()
+    }
+
+    pub fn current_state_concrete<T>(&self) -> Option<&T>
+    where
+        DynData: Cast<T>,
+        T: 'static,
+    {
+        DynData::downcast_ref(self.current_state())
+    }
+
+    /// Returns the context of the state machine.
+    pub fn ctx(&self) -> &Ctx {
+        &self.ctx
+    }
+
+    /// Returns the context of the state machine for modification.
+    pub fn ctx_mut(&mut self) -> &mut Ctx {
+        &mut self.ctx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::InitialPseudoState;
+    use crate::{AsyncSmBuilder, BuildError, EnterSmEvent, SmError};
+    use std::any::Any;
+
+    #[test]
+    fn test_async_state_machine() {
+        use crate::async_sm::{atrans, AsyncState, AsyncVertex};
+        use crate::guard;
+        use crate::state::TerminatePseudoState;
+        use std::future::Future;
+        use std::pin::{pin, Pin};
+        use std::task::{Context, Poll, Waker};
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let mut future = pin!(future);
+            let mut cx = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+        }
+
+        // Returns `Pending` once, so that every action is really suspended.
+        struct YieldNow(bool);
+        impl Future for YieldNow {
+            type Output = ();
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                if self.0 {
+                    return Poll::Ready(());
+                }
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        type Log = Vec<String>;
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Busy(u32);
+        #[derive(Debug, PartialEq)]
+        struct Done;
+        #[derive(Debug, PartialEq)]
+        struct Start(u32);
+        #[derive(Debug, PartialEq)]
+        struct Finish;
+
+        let mut sm = block_on(
+            AsyncSmBuilder::<dyn Any, Log>::new()
+                .register_vertex(
+                    AsyncState::<Idle, Log>::new()
+                        .with_exit(async |_, log: &mut Log| {
+                            YieldNow(false).await;
+                            log.push("exit Idle".to_string());
+                        })
+                        .to_vertex(),
+                )
+                .register_vertex(
+                    AsyncState::<Busy, Log>::new()
+                        .with_entry(async |busy, log: &mut Log| {
+                            YieldNow(false).await;
+                            log.push(format!("enter Busy({})", busy.0));
+                        })
+                        .to_vertex(),
+                )
+                .register_vertex(AsyncState::<Done, Log>::new().to_final_vertex())
+                .transition(atrans(async |_: InitialPseudoState, _: EnterSmEvent| Idle))
+                .transition(
+                    atrans(async |_: Idle, start: Start, log: &mut Log| {
+                        YieldNow(false).await;
+                        log.push(format!("start {}", start.0));
+                        Busy(start.0)
+                    })
+                    .guard(guard::with_ctx(
+                        async |start: &Start, log: &mut Log| {
+                            YieldNow(false).await;
+                            log.push(format!("check {}", start.0));
+                            start.0 > 0
+                        },
+                    )),
+                )
+                .transition(atrans(async |_: Busy, _: Finish| Done))
+                .build(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            block_on(sm.process(Start(0))),
+            Err(SmError::NoTransitionSatisfyingEvent(Start(0)))
+        );
+        assert_eq!(sm.current_state_concrete::<Idle>(), Some(&Idle));
+        assert_eq!(block_on(sm.process(Start(3))), Ok(()));
+        assert_eq!(
+            sm.ctx(),
+            &vec![
+                "check 0",
+                "check 3",
+                "exit Idle",
+                "start 3",
+                "enter Busy(3)"
+            ]
+        );
+        assert_eq!(sm.current_state_concrete::<Busy>(), Some(&Busy(3)));
+        assert_eq!(block_on(sm.process(Finish)), Ok(()));
+        assert!(sm.is_finished());
+        assert_eq!(
+            block_on(sm.process(Finish)),
+            Err(SmError::NoTransitionsFromThisVertex(Finish))
+        );
+
+        let sm = block_on(
+            AsyncSmBuilder::<dyn Any>::new()
+                .register_vertex(AsyncVertex::terminate())
+                .transition(atrans(async |_: InitialPseudoState, _: EnterSmEvent| {
+                    TerminatePseudoState
+                }))
+                .build(),
+        )
+        .unwrap();
+        assert!(sm.is_terminated());
+
+        let errors = AsyncSmBuilder::<dyn Any>::new()
+            .transition(atrans(async |_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .validate();
+        assert_eq!(
+            errors,
+            Err(vec![BuildError::MissingOutputVertex(
+                std::any::type_name::<Idle>()
+            )])
+        );
+    }
+}
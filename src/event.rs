@@ -1,6 +1,276 @@
-use std::any::Any;
+use crate::transition::TypeInfo;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::fmt::{Debug, Formatter};
 
 pub type Event = Box<dyn Any>;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct EnterSmEvent;
+
+/// Event that is sent to the composite state when its nested state machine reaches the final
+/// state.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompletionEvent;
+
+/// Enum of all events of the state machine, see `SmBuilder::with_events`. Every variant contains
+/// an event of its own type.
+///
+/// `Sm::process` takes the enum, and transitions registered in the builder with the enum must be
+/// triggered by events of its variants or by internal events, see `EventOf`, which is checked
+/// by the compiler. The event is matched by its variant and boxed only to be passed to the
+/// transitions triggered by its type, so no other transition downcasts it.
+///
+/// Use `#[derive(SmEvent)]` to implement it.
+pub trait SmEvent: Sized + 'static {
+    /// Returns the index of the variant, in the order of `event_types`.
+    fn variant(&self) -> usize;
+    /// Boxes the event contained in the variant.
+    fn into_event(self) -> Event;
+    /// Wraps the boxed event back into the variant with the index `variant`.
+    ///
+    /// Panics if the event is not of the type of the variant.
+    fn from_event(variant: usize, event: Event) -> Self;
+    /// Returns types of events of all variants.
+    fn event_types() -> Vec<TypeInfo>;
+}
+
+/// Event that triggers transitions of the state machine with the event enum `E`: the type of
+/// a variant of `E`, or an internal event, i.e. `EnterSmEvent` or `CompletionEvent`.
+///
+/// It is implemented by `#[derive(SmEvent)]`.
+pub trait EventOf<E: SmEvent> {}
+
+impl<E: SmEvent> EventOf<E> for EnterSmEvent {}
+
+impl<E: SmEvent> EventOf<E> for CompletionEvent {}
+
+/// Queue of events posted by actions of the state machine, see `SmBuilder::queue`.
+///
+/// The queue is a handle, its clones refer to the same queue, so it can be kept in the context
+/// or captured by actions. Posted events are processed by the state machine only after the
+/// current step is completed. Internal events are processed before external ones, events of the
+/// same priority in the order they are posted.
+#[derive(Clone)]
+pub struct EventQueue(Rc<RefCell<Queues>>);
+
+#[derive(Default)]
+struct Queues {
+    internal: VecDeque<Event>,
+    external: VecDeque<Event>,
+}
+
+impl Debug for EventQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let queues = self.0.borrow();
+        f.debug_struct("EventQueue")
+            .field("internal", &queues.internal.len())
+            .field("external", &queues.external.len())
+            .finish()
+    }
+}
+
+impl EventQueue {
+    pub(crate) fn new() -> Self {
+        EventQueue(Rc::default())
+    }
+
+    /// Posts the internal event, it is processed before all external events.
+    pub fn post<E: Any>(&self, event: E) {
+        self.0.borrow_mut().internal.push_back(Box::new(event));
+    }
+
+    /// Posts the external event, it is processed after all internal events.
+    pub fn post_external<E: Any>(&self, event: E) {
+        self.0.borrow_mut().external.push_back(Box::new(event));
+    }
+
+    /// Returns the number of events waiting in the queue.
+    pub fn len(&self) -> usize {
+        let queues = self.0.borrow();
+        queues.internal.len() + queues.external.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes the next event to process.
+    pub(crate) fn pop(&self) -> Option<Event> {
+        let mut queues = self.0.borrow_mut();
+        match queues.internal.pop_front() {
+            Some(event) => Some(event),
+            None => queues.external.pop_front(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{BuildError, EnterSmEvent, EventQueue, SmBuilder, SmError, SmEvent};
+    use std::any::Any;
+
+    #[test]
+    fn test_event_enum() {
+        use std::any::type_name;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Playing;
+
+        #[derive(Debug, PartialEq)]
+        struct Play;
+        #[derive(Debug, PartialEq)]
+        struct Stop;
+        struct Eject;
+        #[derive(Debug, PartialEq)]
+        struct Stopped;
+        #[derive(Debug, PartialEq)]
+        struct Ejected;
+
+        #[derive(Debug, PartialEq, SmEvent)]
+        enum PlayerEvent {
+            Play(Play),
+            Stop(Stop),
+        }
+
+        let builder = || {
+            SmBuilder::<dyn Any>::new()
+                .with_events::<PlayerEvent>()
+                .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+                .register_vertex(SimpleVertex::<Playing>::new().to_vertex())
+                .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+                .transition(ftrans(|_: Idle, _: Play| Playing))
+                .transition(ftrans(|_: Playing, _: Stop| Idle))
+        };
+
+        let mut sm = builder().build().unwrap();
+        assert_eq!(PlayerEvent::Stop(Stop).variant(), 1);
+        assert_eq!(sm.process(Play.into()), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Playing));
+        assert_eq!(
+            sm.process(PlayerEvent::Play(Play)),
+            Err(SmError::NoTransitionSatisfyingEvent(PlayerEvent::Play(
+                Play
+            )))
+        );
+        assert_eq!(sm.process(Stop.into()), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+
+        // Transitions of nested state machines are not typed by the enum, they are checked when
+        // the state machine is built.
+        assert_eq!(
+            builder()
+                .register_vertex(SimpleVertex::<Stopped>::new().to_submachine_vertex(|| {
+                    SmBuilder::new()
+                        .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+                        .register_vertex(SimpleVertex::<Ejected>::new().to_final_vertex())
+                        .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+                        .transition(ftrans(|_: Idle, _: Eject| Ejected))
+                }))
+                .transition(ftrans(|_: Idle, _: Stop| Stopped))
+                .build()
+                .unwrap_err(),
+            vec![BuildError::InvalidSubmachine {
+                state: type_name::<Stopped>(),
+                errors: vec![BuildError::UnexpectedEvent(type_name::<Eject>())]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_event_queue() {
+        struct Ctx {
+            queue: EventQueue,
+            log: Vec<String>,
+        }
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Working;
+        #[derive(Debug, PartialEq)]
+        struct Start;
+        #[derive(Debug, PartialEq)]
+        struct Step(u32);
+        #[derive(Debug, PartialEq)]
+        struct Stop;
+
+        let mut sm = SmBuilder::<dyn Any, Ctx>::new()
+            .register_vertex(SimpleVertex::<Idle, Ctx>::new().to_vertex())
+            .register_vertex(
+                SimpleVertex::<Working, Ctx>::new()
+                    .with_entry_ctx(|_, ctx| ctx.log.push("enter Working".to_string()))
+                    .to_vertex(),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Start, ctx: &mut Ctx| {
+                ctx.queue.post_external(Stop);
+                ctx.queue.post(Step(1));
+                ctx.log.push("start".to_string());
+                Working
+            }))
+            .transition(ftrans(|_: Working, step: Step, ctx: &mut Ctx| {
+                if step.0 < 2 {
+                    ctx.queue.post(Step(step.0 + 1));
+                }
+                ctx.log.push(format!("step {}", step.0));
+                Working
+            }))
+            .transition(ftrans(|_: Working, _: Stop, ctx: &mut Ctx| {
+                ctx.log.push("stop".to_string());
+                Idle
+            }))
+            .build_with_queue(|queue| Ctx { queue, log: vec![] })
+            .unwrap();
+
+        assert_eq!(sm.process(Start), Ok(()));
+        // The internal steps are processed before the external Stop, which was posted first.
+        assert_eq!(
+            sm.ctx_mut().unwrap().log.split_off(0),
+            vec![
+                "start",
+                "enter Working",
+                "step 1",
+                "enter Working",
+                "step 2",
+                "enter Working",
+                "stop"
+            ]
+        );
+        assert!(sm.queue().is_empty());
+        assert!(sm.take_errors().is_empty());
+        assert_eq!(sm.current_state_concrete::<Idle>(), Some(&Idle));
+
+        // The internal Stop is processed before the external Start and is rejected in Idle.
+        sm.queue().post_external(Start);
+        sm.queue().post(Stop);
+        assert_eq!(sm.queue().len(), 2);
+        let errors: Vec<_> = sm
+            .process_queue()
+            .into_iter()
+            .map(|err| err.map(|e| *e.downcast::<Stop>().unwrap()))
+            .collect();
+        assert_eq!(errors, vec![SmError::NoTransitionSatisfyingEvent(Stop)]);
+        assert!(sm.queue().is_empty());
+        assert_eq!(sm.current_state_concrete::<Idle>(), Some(&Idle));
+        assert_eq!(
+            sm.ctx().unwrap().log,
+            vec![
+                "start",
+                "enter Working",
+                "step 1",
+                "enter Working",
+                "step 2",
+                "enter Working",
+                "stop"
+            ]
+        );
+    }
+}
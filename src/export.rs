@@ -0,0 +1,321 @@
+//! Export of state machines to the textual diagram formats.
+
+mod dot;
+mod mermaid;
+mod plantuml;
+mod scxml;
+
+use crate::event::EnterSmEvent;
+use crate::junction::{Compound, CompoundKind};
+use crate::transition::{Transition, TypeInfo};
+use crate::vertex::{PseudoStateKind, StateTrait, Vertex};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::TypeId;
+
+/// Format-independent description of the state machine graph.
+pub(crate) struct Diagram {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) edges: Vec<Edge>,
+    /// Index of the active node.
+    pub(crate) current: Option<usize>,
+}
+
+pub(crate) struct Node {
+    /// Name of the vertex data type.
+    pub(crate) name: &'static str,
+    pub(crate) kind: NodeKind,
+    pub(crate) entry: bool,
+    pub(crate) exit: bool,
+    /// Nested state machines of the composite state.
+    pub(crate) regions: Vec<Diagram>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NodeKind {
+    Initial,
+    State,
+    /// State with the nested state machine.
+    Composite,
+    /// State with orthogonal regions.
+    Orthogonal,
+    Final,
+    Terminate,
+    ShallowHistory,
+    DeepHistory,
+    Junction,
+    Choice,
+    Entry,
+    Exit,
+}
+
+pub(crate) struct Edge {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    /// Name of the event type.
+    pub(crate) event: &'static str,
+    pub(crate) guarded: bool,
+    pub(crate) guards: Vec<EdgeGuard>,
+}
+
+impl Edge {
+    /// Returns the label of guards of the edge, like `[even && guarded]`. Guards without names
+    /// are labelled as `guarded`.
+    pub(crate) fn guard_label(&self) -> String {
+        let names: Vec<&str> = self
+            .guards
+            .iter()
+            .map(|guard| guard.name.as_deref().unwrap_or("guarded"))
+            .collect();
+        match names.is_empty() {
+            true => "[guarded]".to_string(),
+            false => format!("[{}]", names.join(" && ")),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct EdgeGuard {
+    /// Name of the guard, see `Guard::name`.
+    pub(crate) name: Option<String>,
+    /// Identifier of the guard, see `Diagram::guards`.
+    pub(crate) id: String,
+}
+
+/// Numbers of unnamed guards by the source node and the event, see `Diagram::guards`.
+type Unnamed = BTreeMap<(usize, &'static str), usize>;
+
+impl Diagram {
+    /// Describes the vertexes and transitions. Transitions from or to unregistered vertexes are
+    /// skipped.
+    pub(crate) fn new<DynData: ?Sized + 'static, Ctx: 'static>(
+        vertexes: &[Vertex<DynData, Ctx>],
+        transitions: &BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+        current: Option<usize>,
+    ) -> Self {
+        let find = |tid: TypeId| vertexes.iter().position(|v| v.data_tid() == tid);
+        let nodes = vertexes
+            .iter()
+            .map(|vertex| Node {
+                name: vertex.data_type_name(),
+                kind: NodeKind::of(vertex),
+                entry: vertex.has_entry(),
+                exit: vertex.has_exit(),
+                regions: vertex
+                    .regions()
+                    .iter()
+                    .map(|region| region.machine().diagram())
+                    .collect(),
+            })
+            .collect();
+        let mut diagram = Diagram {
+            nodes,
+            edges: vec![],
+            current,
+        };
+        let mut unnamed = Unnamed::new();
+        for (from, vertex) in vertexes.iter().enumerate() {
+            if find(vertex.data_tid()) != Some(from) {
+                continue;
+            }
+            for transition in transitions.get(&vertex.data_tid()).into_iter().flatten() {
+                let event = transition.event_type().name;
+                if let Some(compound) = transition.compound() {
+                    diagram.add_compound(from, event, compound, &find, &mut unnamed);
+                    continue;
+                }
+                let guards = diagram.guards(from, event, transition.guards(), &mut unnamed);
+                for output in transition.output_types() {
+                    if let Some(to) = find(output.tid) {
+                        diagram.edges.push(Edge {
+                            from,
+                            to,
+                            event,
+                            guarded: transition.is_guarded(),
+                            guards: guards.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        diagram
+    }
+
+    /// Describes the compound transition from the node `from` as the junction or choice node
+    /// with the incoming edge and edges of the outgoing segments. The node is shared by all
+    /// compound transitions through the pseudo-state with the same data type.
+    fn add_compound(
+        &mut self,
+        from: usize,
+        event: &'static str,
+        compound: Compound,
+        find: &dyn Fn(TypeId) -> Option<usize>,
+        unnamed: &mut Unnamed,
+    ) {
+        let kind = match compound.kind {
+            CompoundKind::Junction => NodeKind::Junction,
+            CompoundKind::Choice => NodeKind::Choice,
+        };
+        let name = compound.pseudo_state.name;
+        let pseudo_state = match self
+            .nodes
+            .iter()
+            .position(|n| n.kind == kind && n.name == name)
+        {
+            Some(i) => i,
+            None => {
+                self.nodes.push(Node {
+                    name,
+                    kind,
+                    entry: false,
+                    exit: false,
+                    regions: vec![],
+                });
+                self.nodes.len() - 1
+            }
+        };
+        let guards = self.guards(from, event, compound.guards, unnamed);
+        self.edges.push(Edge {
+            from,
+            to: pseudo_state,
+            event,
+            guarded: !guards.is_empty(),
+            guards,
+        });
+        let event = TypeInfo::of::<EnterSmEvent>().name;
+        for segment in compound.segments {
+            let guards = self.guards(pseudo_state, event, segment.guards, unnamed);
+            for output in &segment.outputs {
+                if let Some(to) = find(output.tid) {
+                    self.edges.push(Edge {
+                        from: pseudo_state,
+                        to,
+                        event,
+                        guarded: !guards.is_empty(),
+                        guards: guards.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Describes guards of the transition from the node `from` on `event`. Named guards are
+    /// identified by their names. Guards without names are identified by the source
+    /// node, the event and their number among unnamed guards of the transitions from the node on
+    /// the event, so identifiers are unique in the diagram and stay the same while transitions
+    /// are registered in the same order.
+    fn guards(
+        &self,
+        from: usize,
+        event: &'static str,
+        names: Vec<Option<&str>>,
+        unnamed: &mut Unnamed,
+    ) -> Vec<EdgeGuard> {
+        names
+            .into_iter()
+            .map(|name| match name {
+                Some(name) => EdgeGuard {
+                    name: Some(name.to_string()),
+                    id: name.to_string(),
+                },
+                None => {
+                    let n = unnamed.entry((from, event)).or_default();
+                    *n += 1;
+                    EdgeGuard {
+                        name: None,
+                        id: format!(
+                            "{}.{}.guard{}",
+                            sanitize(self.nodes[from].name),
+                            sanitize(event),
+                            n
+                        ),
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Returns nodes every region of the orthogonal state `i` starts in, if the state is entered
+    /// by some edge and the only initial edge of every region leads to a state with its own
+    /// identifier. Exporters draw entering such state as a fork.
+    pub(crate) fn fork(&self, i: usize) -> Option<Vec<usize>> {
+        let node = &self.nodes[i];
+        if node.kind != NodeKind::Orthogonal || !self.edges.iter().any(|e| e.to == i) {
+            return None;
+        }
+        node.regions
+            .iter()
+            .map(|region| {
+                let mut initial = region
+                    .edges
+                    .iter()
+                    .filter(|e| region.nodes[e.from].kind == NodeKind::Initial);
+                match (initial.next(), initial.next()) {
+                    (Some(edge), None) if region.nodes[edge.to].kind.has_id() => Some(edge.to),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the node is the junction or choice pseudo-state, whose outgoing edges
+    /// are segments of the compound transitions and are not triggered by events.
+    pub(crate) fn is_segment(&self, edge: &Edge) -> bool {
+        matches!(
+            self.nodes[edge.from].kind,
+            NodeKind::Junction | NodeKind::Choice
+        )
+    }
+}
+
+/// Makes the valid SCXML identifier from the name of the type.
+pub(crate) fn sanitize(name: &str) -> String {
+    let name = name.replace("::", ".");
+    let mut id: String = name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        id.insert(0, '_');
+    }
+    id
+}
+
+impl NodeKind {
+    /// Returns `false` for nodes that exporters draw with the shared symbol instead of the
+    /// identifier, e.g. `[*]`.
+    fn has_id(self) -> bool {
+        !matches!(
+            self,
+            NodeKind::Initial | NodeKind::Final | NodeKind::ShallowHistory | NodeKind::DeepHistory
+        )
+    }
+
+    fn of<DynData: ?Sized, Ctx>(vertex: &Vertex<DynData, Ctx>) -> Self {
+        match vertex {
+            Vertex::State(_) => NodeKind::State,
+            Vertex::SubMachineState(_) => NodeKind::Composite,
+            Vertex::OrthogonalState(_) => NodeKind::Orthogonal,
+            Vertex::FinalState(_) => NodeKind::Final,
+            Vertex::PseudoState(ps) => match ps.kind {
+                PseudoStateKind::Initial => NodeKind::Initial,
+                PseudoStateKind::Terminate => NodeKind::Terminate,
+                PseudoStateKind::ShallowHistory => NodeKind::ShallowHistory,
+                PseudoStateKind::DeepHistory => NodeKind::DeepHistory,
+                PseudoStateKind::Junction => NodeKind::Junction,
+                PseudoStateKind::Choice => NodeKind::Choice,
+                PseudoStateKind::Entry(_) => NodeKind::Entry,
+                PseudoStateKind::Exit(_) => NodeKind::Exit,
+            },
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::export::{Diagram, NodeKind};
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+impl Diagram {
+    /// Renders the diagram in the Graphviz DOT language.
+    ///
+    /// Guarded edges are dashed, the current state is filled.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut out = String::from("digraph {\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let shape = match node.kind {
+                NodeKind::Initial => "shape=point",
+                NodeKind::State | NodeKind::Composite | NodeKind::Orthogonal => {
+                    "shape=box, style=rounded"
+                }
+                NodeKind::Final => "shape=doublecircle",
+                NodeKind::Terminate => "shape=Mcircle",
+                NodeKind::ShallowHistory | NodeKind::DeepHistory => "shape=circle",
+                NodeKind::Junction => "shape=circle, width=0.2",
+                NodeKind::Choice => "shape=diamond",
+                NodeKind::Entry | NodeKind::Exit => "shape=circle, width=0.2",
+            };
+            let current = match self.current == Some(i) {
+                true => ", style=\"rounded,filled\", fillcolor=lightblue",
+                false => "",
+            };
+            let label = match node.kind {
+                NodeKind::Initial => String::new(),
+                _ => format!(", label={}", quote(node.name)),
+            };
+            writeln!(out, "    n{} [{}{}{}];", i, shape, label, current).unwrap();
+        }
+        for edge in &self.edges {
+            let style = match edge.guarded {
+                true => ", style=dashed",
+                false => "",
+            };
+            let label = match (self.is_segment(edge), edge.guarded) {
+                (true, true) => quote(&edge.guard_label()),
+                (true, false) => quote(""),
+                (false, true) => quote(&format!("{} {}", edge.event, edge.guard_label())),
+                (false, false) => quote(edge.event),
+            };
+            writeln!(
+                out,
+                "    n{} -> n{} [label={}{}];",
+                edge.from, edge.to, label, style
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::GuardedTransition;
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{EnterSmEvent, SmBuilder};
+    use std::any::Any;
+
+    #[test]
+    fn test_dot() {
+        use std::any::type_name;
+
+        struct A;
+        struct B;
+        struct Done;
+
+        let builder = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<A>::new().to_vertex())
+            .register_vertex(SimpleVertex::<B>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| A))
+            .transition(
+                GuardedTransition::new()
+                    .guard(|e: &i32| *e > 0)
+                    .transition(ftrans(|_: A, _: i32| B)),
+            )
+            .transition(ftrans(|_: B, _: u32| Done));
+
+        let dot = |current: &str| {
+            format!(
+                concat!(
+                    "digraph {{\n",
+                    "    n0 [shape=point];\n",
+                    "    n1 [shape=box, style=rounded, label=\"{a}\"{current}];\n",
+                    "    n2 [shape=box, style=rounded, label=\"{b}\"];\n",
+                    "    n3 [shape=doublecircle, label=\"{done}\"];\n",
+                    "    n0 -> n1 [label=\"{enter}\"];\n",
+                    "    n1 -> n2 [label=\"i32 [guarded]\", style=dashed];\n",
+                    "    n2 -> n3 [label=\"u32\"];\n",
+                    "}}\n",
+                ),
+                a = type_name::<A>(),
+                b = type_name::<B>(),
+                done = type_name::<Done>(),
+                enter = type_name::<EnterSmEvent>(),
+                current = current,
+            )
+        };
+
+        assert_eq!(builder.to_dot(), dot(""));
+        let sm = builder.build().unwrap();
+        assert_eq!(
+            sm.to_dot(),
+            dot(", style=\"rounded,filled\", fillcolor=lightblue")
+        );
+    }
+}
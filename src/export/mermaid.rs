@@ -0,0 +1,275 @@
+use crate::export::{Diagram, NodeKind};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+impl Diagram {
+    /// Renders the diagram as a Mermaid `stateDiagram-v2`.
+    ///
+    /// The initial pseudo-state and final states are rendered as `[*]`, junction and choice
+    /// pseudo-states as `<<choice>>` nodes. Regions of the orthogonal states are separated by
+    /// `--`, and transitions to them pass the `<<fork>>` node, see `Diagram::fork`. The current
+    /// state gets the `current` class.
+    pub(crate) fn to_mermaid(&self) -> String {
+        let mut out = String::from("stateDiagram-v2\n");
+        self.write_mermaid(&mut out, "n", 1, false);
+        if let Some(current) = self.current {
+            out.push_str("    classDef current fill:lightblue\n");
+            writeln!(out, "    class n{} current", current).unwrap();
+        }
+        out
+    }
+
+    /// Writes nodes and edges with identifiers starting with `prefix`. If the diagram is a
+    /// region of the forked orthogonal state, its initial edges are drawn by the fork.
+    fn write_mermaid(&self, out: &mut String, prefix: &str, depth: usize, forked: bool) {
+        let indent = "    ".repeat(depth);
+        let id = |i: usize| match self.nodes[i].kind {
+            NodeKind::Initial | NodeKind::Final => "[*]".to_string(),
+            _ => format!("{}{}", prefix, i),
+        };
+        let forks: Vec<_> = (0..self.nodes.len())
+            .map(|i| self.fork(i).map(|targets| (i, targets)))
+            .collect();
+        let target = |i: usize| match &forks[i] {
+            Some(_) => format!("{}_fork", id(i)),
+            None => id(i),
+        };
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = match node.kind {
+                NodeKind::Initial | NodeKind::Final => continue,
+                NodeKind::Junction | NodeKind::Choice => {
+                    writeln!(out, "{}state {} <<choice>>", indent, id(i)).unwrap();
+                    continue;
+                }
+                NodeKind::ShallowHistory => "H",
+                NodeKind::DeepHistory => "H*",
+                _ => node.name,
+            };
+            if forks[i].is_some() {
+                writeln!(out, "{}state {} <<fork>>", indent, target(i)).unwrap();
+            }
+            writeln!(out, "{}state \"{}\" as {}", indent, label, id(i)).unwrap();
+            if !node.regions.is_empty() {
+                writeln!(out, "{}state {} {{", indent, id(i)).unwrap();
+                for (r, region) in node.regions.iter().enumerate() {
+                    if r > 0 {
+                        writeln!(out, "{}    --", indent).unwrap();
+                    }
+                    let prefix = format!("{}{}_{}_", prefix, i, r);
+                    region.write_mermaid(out, &prefix, depth + 1, forks[i].is_some());
+                }
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+        }
+
+        for edge in &self.edges {
+            let from = self.nodes[edge.from].kind;
+            if forked && from == NodeKind::Initial {
+                continue;
+            }
+            write!(out, "{}{} --> {}", indent, id(edge.from), target(edge.to)).unwrap();
+            match (
+                from == NodeKind::Initial || self.is_segment(edge),
+                edge.guarded,
+            ) {
+                (true, false) => out.push('\n'),
+                (true, true) => writeln!(out, " : {}", edge.guard_label()).unwrap(),
+                (false, false) => writeln!(out, " : {}", edge.event).unwrap(),
+                (false, true) => writeln!(out, " : {} {}", edge.event, edge.guard_label()).unwrap(),
+            }
+        }
+        for (i, targets) in forks.iter().flatten() {
+            for (r, to) in targets.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{}{} --> {}{}_{}_{}",
+                    indent,
+                    target(*i),
+                    prefix,
+                    i,
+                    r,
+                    to
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{EnterSmEvent, SmBuilder, Vertex};
+    use std::any::Any;
+
+    #[test]
+    fn test_mermaid() {
+        use crate::state::OrthogonalVertex;
+        use std::any::type_name;
+
+        struct Device;
+        struct Off;
+        struct Down;
+        struct Stopped;
+
+        let sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(
+                OrthogonalVertex::new(SimpleVertex::<Device>::new())
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Off>::new().to_final_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Off))
+                    })
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Down>::new().to_vertex())
+                            .register_vertex(Vertex::shallow_history())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Down))
+                            .transition(ftrans(|_: Down, _: u8| Down))
+                    })
+                    .to_vertex(),
+            )
+            .register_vertex(SimpleVertex::<Stopped>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Device))
+            .transition(ftrans(|_: Device, _: i32| Stopped))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sm.to_mermaid(),
+            format!(
+                concat!(
+                    "stateDiagram-v2\n",
+                    "    state \"{device}\" as n1\n",
+                    "    state n1 {{\n",
+                    "        [*] --> [*]\n",
+                    "        --\n",
+                    "        state \"{down}\" as n1_1_1\n",
+                    "        state \"H\" as n1_1_2\n",
+                    "        [*] --> n1_1_1\n",
+                    "        n1_1_1 --> n1_1_1 : u8\n",
+                    "    }}\n",
+                    "    [*] --> n1\n",
+                    "    n1 --> [*] : i32\n",
+                    "    classDef current fill:lightblue\n",
+                    "    class n1 current\n",
+                ),
+                device = type_name::<Device>(),
+                down = type_name::<Down>(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_mermaid_junction() {
+        use crate::guard::named;
+        use crate::junction::Junction;
+        use std::any::type_name;
+
+        struct Idle;
+        struct Doubled;
+        struct Big;
+        struct Small;
+
+        let sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Big>::new().to_final_vertex())
+            .register_vertex(SimpleVertex::<Small>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(
+                Junction::new(ftrans(|_: Idle, _: u32| Doubled))
+                    .branch(
+                        named("big", |n: &u32| *n > 10),
+                        ftrans(|_: Doubled, _: EnterSmEvent| Big),
+                    )
+                    .otherwise(ftrans(|_: Doubled, _: EnterSmEvent| Small)),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sm.to_mermaid(),
+            format!(
+                concat!(
+                    "stateDiagram-v2\n",
+                    "    state \"{idle}\" as n1\n",
+                    "    state n4 <<choice>>\n",
+                    "    [*] --> n1\n",
+                    "    n1 --> n4 : u32\n",
+                    "    n4 --> [*] : [big]\n",
+                    "    n4 --> [*]\n",
+                    "    classDef current fill:lightblue\n",
+                    "    class n1 current\n",
+                ),
+                idle = type_name::<Idle>(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_mermaid_fork() {
+        use crate::state::OrthogonalVertex;
+        use std::any::type_name;
+
+        struct Idle;
+        struct Device;
+        struct Off;
+        struct Down;
+
+        let sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(
+                OrthogonalVertex::new(SimpleVertex::<Device>::new())
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Off>::new().to_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Off))
+                            .transition(ftrans(|_: Off, _: u8| Off))
+                    })
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Down>::new().to_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Down))
+                            .transition(ftrans(|_: Down, _: u16| Down))
+                    })
+                    .to_vertex(),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: i32| Device))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sm.to_mermaid(),
+            format!(
+                concat!(
+                    "stateDiagram-v2\n",
+                    "    state \"{idle}\" as n1\n",
+                    "    state n2_fork <<fork>>\n",
+                    "    state \"{device}\" as n2\n",
+                    "    state n2 {{\n",
+                    "        state \"{off}\" as n2_0_1\n",
+                    "        n2_0_1 --> n2_0_1 : u8\n",
+                    "        --\n",
+                    "        state \"{down}\" as n2_1_1\n",
+                    "        n2_1_1 --> n2_1_1 : u16\n",
+                    "    }}\n",
+                    "    [*] --> n1\n",
+                    "    n1 --> n2_fork : i32\n",
+                    "    n2_fork --> n2_0_1\n",
+                    "    n2_fork --> n2_1_1\n",
+                    "    classDef current fill:lightblue\n",
+                    "    class n1 current\n",
+                ),
+                idle = type_name::<Idle>(),
+                device = type_name::<Device>(),
+                off = type_name::<Off>(),
+                down = type_name::<Down>(),
+            )
+        );
+    }
+}
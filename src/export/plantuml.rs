@@ -0,0 +1,266 @@
+use crate::export::{Diagram, NodeKind};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+impl Diagram {
+    /// Renders the diagram as a PlantUML state diagram.
+    ///
+    /// The initial pseudo-state and final states are rendered as `[*]`, history pseudo-states as
+    /// `[H]` and `[H*]`. Regions of the orthogonal states are separated by `--`, and
+    /// transitions to them pass the `<<fork>>` node, see `Diagram::fork`.
+    pub(crate) fn to_plantuml(&self) -> String {
+        let mut out = String::from("@startuml\n");
+        self.write_plantuml(&mut out, "n", 0, false);
+        out.push_str("@enduml\n");
+        out
+    }
+
+    /// Writes nodes and edges with identifiers starting with `prefix`. If the diagram is a
+    /// region of the forked orthogonal state, its initial edges are drawn by the fork.
+    fn write_plantuml(&self, out: &mut String, prefix: &str, depth: usize, forked: bool) {
+        let indent = "    ".repeat(depth);
+        let id = |i: usize| match self.nodes[i].kind {
+            NodeKind::Initial | NodeKind::Final => "[*]".to_string(),
+            NodeKind::ShallowHistory => "[H]".to_string(),
+            NodeKind::DeepHistory => "[H*]".to_string(),
+            _ => format!("{}{}", prefix, i),
+        };
+        let forks: Vec<_> = (0..self.nodes.len())
+            .map(|i| self.fork(i).map(|targets| (i, targets)))
+            .collect();
+        let target = |i: usize| match &forks[i] {
+            Some(_) => format!("{}_fork", id(i)),
+            None => id(i),
+        };
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let stereotype = match node.kind {
+                NodeKind::Initial
+                | NodeKind::Final
+                | NodeKind::ShallowHistory
+                | NodeKind::DeepHistory => continue,
+                NodeKind::State | NodeKind::Composite | NodeKind::Orthogonal => "",
+                NodeKind::Terminate => " <<end>>",
+                NodeKind::Junction | NodeKind::Choice => " <<choice>>",
+                NodeKind::Entry => " <<entryPoint>>",
+                NodeKind::Exit => " <<exitPoint>>",
+            };
+            if forks[i].is_some() {
+                writeln!(out, "{}state {} <<fork>>", indent, target(i)).unwrap();
+            }
+            let color = match self.current == Some(i) {
+                true => " #lightblue",
+                false => "",
+            };
+            write!(
+                out,
+                "{}state \"{}\" as {}{}{}",
+                indent,
+                node.name,
+                id(i),
+                stereotype,
+                color
+            )
+            .unwrap();
+            match node.regions.is_empty() {
+                true => out.push('\n'),
+                false => {
+                    out.push_str(" {\n");
+                    for (r, region) in node.regions.iter().enumerate() {
+                        if r > 0 {
+                            writeln!(out, "{}    --", indent).unwrap();
+                        }
+                        let prefix = format!("{}{}_{}_", prefix, i, r);
+                        region.write_plantuml(out, &prefix, depth + 1, forks[i].is_some());
+                    }
+                    writeln!(out, "{}}}", indent).unwrap();
+                }
+            }
+            if node.entry {
+                writeln!(out, "{}{} : entry", indent, id(i)).unwrap();
+            }
+            if node.exit {
+                writeln!(out, "{}{} : exit", indent, id(i)).unwrap();
+            }
+        }
+
+        for edge in &self.edges {
+            let from = self.nodes[edge.from].kind;
+            if forked && from == NodeKind::Initial {
+                continue;
+            }
+            write!(out, "{}{} --> {}", indent, id(edge.from), target(edge.to)).unwrap();
+            match (
+                from == NodeKind::Initial || self.is_segment(edge),
+                edge.guarded,
+            ) {
+                (true, false) => out.push('\n'),
+                (true, true) => writeln!(out, " : {}", edge.guard_label()).unwrap(),
+                (false, false) => writeln!(out, " : {}", edge.event).unwrap(),
+                (false, true) => writeln!(out, " : {} {}", edge.event, edge.guard_label()).unwrap(),
+            }
+        }
+        for (i, targets) in forks.iter().flatten() {
+            for (r, to) in targets.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{}{} --> {}{}_{}_{}",
+                    indent,
+                    target(*i),
+                    prefix,
+                    i,
+                    r,
+                    to
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::GuardedTransition;
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{CompletionEvent, EnterSmEvent, SmBuilder};
+    use std::any::Any;
+
+    #[test]
+    fn test_plantuml() {
+        use std::any::type_name;
+
+        struct A;
+        struct Composite;
+        struct Inner;
+        struct InnerDone;
+        struct Done;
+
+        let sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<A>::new().with_entry(|_| ()).to_vertex())
+            .register_vertex(
+                SimpleVertex::<Composite>::new()
+                    .with_exit(|_| ())
+                    .to_submachine_vertex(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Inner>::new().to_vertex())
+                            .register_vertex(SimpleVertex::<InnerDone>::new().to_final_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Inner))
+                            .transition(ftrans(|_: Inner, _: u8| InnerDone))
+                    }),
+            )
+            .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| A))
+            .transition(
+                GuardedTransition::new()
+                    .guard(|e: &i32| *e > 0)
+                    .transition(ftrans(|_: A, _: i32| Composite)),
+            )
+            .transition(ftrans(|_: Composite, _: CompletionEvent| Done))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sm.to_plantuml(),
+            format!(
+                concat!(
+                    "@startuml\n",
+                    "state \"{a}\" as n1 #lightblue\n",
+                    "n1 : entry\n",
+                    "state \"{composite}\" as n2 {{\n",
+                    "    state \"{inner}\" as n2_0_1\n",
+                    "    [*] --> n2_0_1\n",
+                    "    n2_0_1 --> [*] : u8\n",
+                    "}}\n",
+                    "n2 : exit\n",
+                    "[*] --> n1\n",
+                    "n1 --> n2 : i32 [guarded]\n",
+                    "n2 --> [*] : {completion}\n",
+                    "@enduml\n",
+                ),
+                a = type_name::<A>(),
+                composite = type_name::<Composite>(),
+                inner = type_name::<Inner>(),
+                completion = type_name::<CompletionEvent>(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_plantuml_choice_and_fork() {
+        use crate::junction::Choice;
+        use crate::state::OrthogonalVertex;
+        use std::any::type_name;
+
+        struct Idle;
+        struct Checked(u32);
+        struct Device;
+        struct Off;
+        struct Down;
+        struct Rejected;
+
+        let sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(
+                OrthogonalVertex::new(SimpleVertex::<Device>::new())
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Off>::new().to_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Off))
+                            .transition(ftrans(|_: Off, _: u8| Off))
+                    })
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Down>::new().to_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Down))
+                            .transition(ftrans(|_: Down, _: u16| Down))
+                    })
+                    .to_vertex(),
+            )
+            .register_vertex(SimpleVertex::<Rejected>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(
+                Choice::new(ftrans(|_: Idle, n: u32| Checked(n)))
+                    .branch(
+                        |c: &Checked| c.0 > 0,
+                        ftrans(|_: Checked, _: EnterSmEvent| Device),
+                    )
+                    .otherwise(ftrans(|_: Checked, _: EnterSmEvent| Rejected)),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sm.to_plantuml(),
+            format!(
+                concat!(
+                    "@startuml\n",
+                    "state \"{idle}\" as n1 #lightblue\n",
+                    "state n2_fork <<fork>>\n",
+                    "state \"{device}\" as n2 {{\n",
+                    "    state \"{off}\" as n2_0_1\n",
+                    "    n2_0_1 --> n2_0_1 : u8\n",
+                    "    --\n",
+                    "    state \"{down}\" as n2_1_1\n",
+                    "    n2_1_1 --> n2_1_1 : u16\n",
+                    "}}\n",
+                    "state \"{checked}\" as n4 <<choice>>\n",
+                    "[*] --> n1\n",
+                    "n1 --> n4 : u32\n",
+                    "n4 --> n2_fork : [guarded]\n",
+                    "n4 --> [*]\n",
+                    "n2_fork --> n2_0_1\n",
+                    "n2_fork --> n2_1_1\n",
+                    "@enduml\n",
+                ),
+                idle = type_name::<Idle>(),
+                device = type_name::<Device>(),
+                off = type_name::<Off>(),
+                down = type_name::<Down>(),
+                checked = type_name::<Checked>(),
+            )
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::export::{sanitize, Diagram, NodeKind};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+impl Diagram {
+    /// Renders the topology of the diagram as a SCXML document.
+    ///
+    /// Identifiers of states and names of events are made from names of their types, e.g.
+    /// `my_crate::Idle` becomes `my_crate.Idle`. Guards are written to the `cond` attribute by
+    /// their identifiers joined with `&&`: named guards by their names, unnamed ones by identifiers
+    /// made from the source state, the event and the number of the guard, e.g.
+    /// `my_crate.Idle.my_crate.Start.guard1`. Terminate pseudo-states are written as `<final>`, and
+    /// every orthogonal region is written as a `<state>` inside the `<parallel>`.
+    pub(crate) fn to_scxml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<scxml xmlns=\"http://www.w3.org/2005/07/scxml\" version=\"1.0\"");
+        self.write_initial(&mut out, "scxml");
+        out.push_str(">\n");
+        self.write_scxml(&mut out, "scxml", 1);
+        out.push_str("</scxml>\n");
+        out
+    }
+
+    /// Writes the `initial` attribute of the element `parent`, that contains the diagram.
+    fn write_initial(&self, out: &mut String, parent: &str) {
+        let initial = self
+            .edges
+            .iter()
+            .find(|e| self.nodes[e.from].kind == NodeKind::Initial);
+        if let Some(edge) = initial {
+            write!(out, " initial=\"{}\"", self.id(edge.to, parent)).unwrap();
+        }
+    }
+
+    fn write_scxml(&self, out: &mut String, parent: &str, depth: usize) {
+        let indent = "    ".repeat(depth);
+        for (i, node) in self.nodes.iter().enumerate() {
+            let id = self.id(i, parent);
+            let edges: Vec<_> = self.edges.iter().filter(|e| e.from == i).collect();
+            let (element, extra) = match node.kind {
+                NodeKind::Initial => continue,
+                NodeKind::Final | NodeKind::Terminate => ("final", String::new()),
+                NodeKind::ShallowHistory => ("history", " type=\"shallow\"".to_string()),
+                NodeKind::DeepHistory => ("history", " type=\"deep\"".to_string()),
+                NodeKind::Orthogonal => ("parallel", String::new()),
+                NodeKind::Composite => {
+                    let mut initial = String::new();
+                    node.regions[0].write_initial(&mut initial, &id);
+                    ("state", initial)
+                }
+                _ => ("state", String::new()),
+            };
+            write!(out, "{}<{} id=\"{}\"{}", indent, element, id, extra).unwrap();
+            if edges.is_empty() && node.regions.is_empty() {
+                out.push_str("/>\n");
+                continue;
+            }
+            out.push_str(">\n");
+
+            match node.kind {
+                NodeKind::Orthogonal => {
+                    for (r, region) in node.regions.iter().enumerate() {
+                        let region_id = format!("{}.region{}", id, r);
+                        write!(out, "{}    <state id=\"{}\"", indent, region_id).unwrap();
+                        region.write_initial(out, &region_id);
+                        out.push_str(">\n");
+                        region.write_scxml(out, &region_id, depth + 2);
+                        writeln!(out, "{}    </state>", indent).unwrap();
+                    }
+                }
+                _ => {
+                    for region in &node.regions {
+                        region.write_scxml(out, &id, depth + 1);
+                    }
+                }
+            }
+
+            for edge in edges {
+                write!(out, "{}    <transition", indent).unwrap();
+                let eventless =
+                    matches!(node.kind, NodeKind::ShallowHistory | NodeKind::DeepHistory);
+                if !eventless && !self.is_segment(edge) {
+                    write!(out, " event=\"{}\"", sanitize(edge.event)).unwrap();
+                }
+                if !edge.guards.is_empty() {
+                    let ids: Vec<_> = edge.guards.iter().map(|g| g.id.as_str()).collect();
+                    write!(out, " cond=\"{}\"", escape(&ids.join(" && "))).unwrap();
+                }
+                writeln!(out, " target=\"{}\"/>", self.id(edge.to, parent)).unwrap();
+            }
+            writeln!(out, "{}</{}>", indent, element).unwrap();
+        }
+    }
+
+    /// Returns identifier of the node. History pseudo-states are named after the enclosing
+    /// state, because the same type is used for them in every state machine.
+    fn id(&self, i: usize, parent: &str) -> String {
+        match self.nodes[i].kind {
+            NodeKind::ShallowHistory | NodeKind::DeepHistory => format!("{}.history", parent),
+            _ => sanitize(self.nodes[i].name),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::GuardedTransition;
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{CompletionEvent, EnterSmEvent, SmBuilder, Vertex};
+    use std::any::Any;
+
+    #[test]
+    fn test_scxml_export() {
+        use crate::guard::named;
+        use crate::state::ShallowHistoryPseudoState;
+
+        struct Idle;
+        struct Working;
+        struct Step;
+        struct Done;
+
+        struct Start(u32);
+
+        let builder = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Working>::new().to_submachine_vertex(|| {
+                SmBuilder::new()
+                    .register_vertex(SimpleVertex::<Step>::new().to_vertex())
+                    .register_vertex(Vertex::shallow_history())
+                    .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| {
+                        ShallowHistoryPseudoState
+                    }))
+                    .transition(ftrans(|_: ShallowHistoryPseudoState, _: EnterSmEvent| Step))
+                    .transition(ftrans(|_: Step, _: u8| Step))
+            }))
+            .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(
+                GuardedTransition::new()
+                    .guard(named("positive", |e: &Start| e.0 > 0))
+                    .transition(ftrans(|_: Idle, _: Start| Working)),
+            )
+            .transition(ftrans(|_: Working, _: CompletionEvent| Done));
+
+        let id = |name: &str| name.replace("::", ".");
+        assert_eq!(
+            builder.to_scxml(),
+            format!(
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                    "<scxml xmlns=\"http://www.w3.org/2005/07/scxml\" version=\"1.0\" ",
+                    "initial=\"{idle}\">\n",
+                    "    <state id=\"{idle}\">\n",
+                    "        <transition event=\"{start}\" cond=\"positive\" ",
+                    "target=\"{working}\"/>\n",
+                    "    </state>\n",
+                    "    <state id=\"{working}\" initial=\"{working}.history\">\n",
+                    "        <state id=\"{step}\">\n",
+                    "            <transition event=\"u8\" target=\"{step}\"/>\n",
+                    "        </state>\n",
+                    "        <history id=\"{working}.history\" type=\"shallow\">\n",
+                    "            <transition target=\"{step}\"/>\n",
+                    "        </history>\n",
+                    "        <transition event=\"{completion}\" target=\"{done}\"/>\n",
+                    "    </state>\n",
+                    "    <final id=\"{done}\"/>\n",
+                    "</scxml>\n",
+                ),
+                idle = id(std::any::type_name::<Idle>()),
+                working = id(std::any::type_name::<Working>()),
+                step = id(std::any::type_name::<Step>()),
+                done = id(std::any::type_name::<Done>()),
+                start = id(std::any::type_name::<Start>()),
+                completion = id(std::any::type_name::<CompletionEvent>()),
+            )
+        );
+    }
+}
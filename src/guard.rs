@@ -1,70 +1,620 @@
 use crate::event::Event;
+use crate::junction::Compound;
+use crate::state::Cast;
 use crate::transition::{
-    EmptyTransition, Transition, TransitionError, TransitionErrorKind, TransitionOut,
+    EmptyTransition, Transition, TransitionError, TransitionErrorKind, TransitionEvent,
+    TransitionInput, TransitionKind, TransitionOut, TransitionOutput, TypeInfo,
 };
+use crate::vertex::StateTrait;
 use crate::Vertex;
-use std::any::{Any, TypeId};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::OnceCell;
+use core::marker::PhantomData;
 
-pub trait Guard<Event> {
-    fn check(&self, input: &Event) -> bool;
+/// Condition of the transition. `Ctx` is the context of the state machine, see `with_ctx`.
+pub trait Guard<Event, Ctx = ()> {
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool;
+    /// Name of the guard in the exported diagrams and errors. Only guards made by `named` and
+    /// combinations of them have names.
+    fn name(&self) -> Option<&str> {
+        None
+    }
 }
 
-impl<F, Event> Guard<Event> for F
+impl<F, Event, Ctx> Guard<Event, Ctx> for F
 where
     F: Fn(&Event) -> bool,
 {
-    fn check(&self, input: &Event) -> bool {
+    fn check(&self, input: &Event, _: &mut Ctx) -> bool {
         self(input)
     }
 }
 
-pub struct GuardedTransition<FEvent, Tr> {
-    guards: Vec<Box<dyn Guard<FEvent>>>,
-    transition: Tr,
+/// Condition of the transition, that checks data of the source state in addition to the event,
+/// see `GuardedTransition::guard_state`.
+pub trait StateGuard<State: ?Sized, Event, Ctx = ()> {
+    fn check(&self, state: &State, input: &Event, ctx: &mut Ctx) -> bool;
+    /// Name of the guard in the exported diagrams and errors, see `Guard::name`.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl<F, State: ?Sized, Event, Ctx> StateGuard<State, Event, Ctx> for F
+where
+    F: Fn(&State, &Event) -> bool,
+{
+    fn check(&self, state: &State, input: &Event, _: &mut Ctx) -> bool {
+        self(state, input)
+    }
+}
+
+/// Guard that checks the context of the state machine too, see `with_ctx`.
+pub struct CtxGuard<F>(pub(crate) F);
+
+/// Makes the guard from the function, that takes the context of the state machine in addition to
+/// the event, or in addition to the state data and the event.
+pub fn with_ctx<F>(f: F) -> CtxGuard<F> {
+    CtxGuard(f)
+}
+
+impl<F, Event, Ctx> Guard<Event, Ctx> for CtxGuard<F>
+where
+    F: Fn(&Event, &mut Ctx) -> bool,
+{
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        (self.0)(input, ctx)
+    }
+}
+
+impl<F, State: ?Sized, Event, Ctx> StateGuard<State, Event, Ctx> for CtxGuard<F>
+where
+    F: Fn(&State, &Event, &mut Ctx) -> bool,
+{
+    fn check(&self, state: &State, input: &Event, ctx: &mut Ctx) -> bool {
+        (self.0)(state, input, ctx)
+    }
 }
 
-impl<Event> GuardedTransition<Event, EmptyTransition> {
+/// Guard with the explicit name, see `named`.
+pub struct NamedGuard<G> {
+    pub(crate) name: &'static str,
+    pub(crate) guard: G,
+}
+
+/// Gives the guard the name, that is used in the exported diagrams and errors. Guards without
+/// names are labelled as `guarded` in diagrams and identified by generated names in SCXML, see
+/// `Sm::to_scxml`.
+pub fn named<G>(name: &'static str, guard: G) -> NamedGuard<G> {
+    NamedGuard { name, guard }
+}
+
+impl<G, Event, Ctx> Guard<Event, Ctx> for NamedGuard<G>
+where
+    G: Guard<Event, Ctx>,
+{
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        self.guard.check(input, ctx)
+    }
+    fn name(&self) -> Option<&str> {
+        Some(self.name)
+    }
+}
+
+impl<G, State: ?Sized, Event, Ctx> StateGuard<State, Event, Ctx> for NamedGuard<G>
+where
+    G: StateGuard<State, Event, Ctx>,
+{
+    fn check(&self, state: &State, input: &Event, ctx: &mut Ctx) -> bool {
+        self.guard.check(state, input, ctx)
+    }
+    fn name(&self) -> Option<&str> {
+        Some(self.name)
+    }
+}
+
+/// Guard that is satisfied when both guards are satisfied, see `and`.
+pub struct And<A, B> {
+    a: A,
+    b: B,
+    name: OnceCell<Option<String>>,
+}
+
+/// Combines guards, so that both of them must be satisfied. `b` is not checked if `a` is not
+/// satisfied.
+pub fn and<A, B>(a: A, b: B) -> And<A, B> {
+    And {
+        a,
+        b,
+        name: OnceCell::new(),
+    }
+}
+
+impl<A, B, Event, Ctx> Guard<Event, Ctx> for And<A, B>
+where
+    A: Guard<Event, Ctx>,
+    B: Guard<Event, Ctx>,
+{
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        self.a.check(input, ctx) && self.b.check(input, ctx)
+    }
+    fn name(&self) -> Option<&str> {
+        self.name
+            .get_or_init(|| Some(format!("({} && {})", self.a.name()?, self.b.name()?)))
+            .as_deref()
+    }
+}
+
+/// Guard that is satisfied when any of two guards is satisfied, see `or`.
+pub struct Or<A, B> {
+    a: A,
+    b: B,
+    name: OnceCell<Option<String>>,
+}
+
+/// Combines guards, so that one of them must be satisfied. `b` is not checked if `a` is
+/// satisfied.
+pub fn or<A, B>(a: A, b: B) -> Or<A, B> {
+    Or {
+        a,
+        b,
+        name: OnceCell::new(),
+    }
+}
+
+impl<A, B, Event, Ctx> Guard<Event, Ctx> for Or<A, B>
+where
+    A: Guard<Event, Ctx>,
+    B: Guard<Event, Ctx>,
+{
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        self.a.check(input, ctx) || self.b.check(input, ctx)
+    }
+    fn name(&self) -> Option<&str> {
+        self.name
+            .get_or_init(|| Some(format!("({} || {})", self.a.name()?, self.b.name()?)))
+            .as_deref()
+    }
+}
+
+/// Guard that is satisfied when the inner guard is not, see `not`.
+pub struct Not<G> {
+    guard: G,
+    name: OnceCell<Option<String>>,
+}
+
+/// Negates the guard.
+pub fn not<G>(guard: G) -> Not<G> {
+    Not {
+        guard,
+        name: OnceCell::new(),
+    }
+}
+
+impl<G, Event, Ctx> Guard<Event, Ctx> for Not<G>
+where
+    G: Guard<Event, Ctx>,
+{
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        !self.guard.check(input, ctx)
+    }
+    fn name(&self) -> Option<&str> {
+        self.name
+            .get_or_init(|| Some(format!("!{}", self.guard.name()?)))
+            .as_deref()
+    }
+}
+
+/// Guard that is satisfied when any of the guards is satisfied, see `any_of`.
+pub struct AnyOf<Event, Ctx = ()> {
+    guards: Vec<Box<dyn Guard<Event, Ctx>>>,
+    name: OnceCell<Option<String>>,
+}
+
+/// Combines guards, so that at least one of them must be satisfied. Guards are checked in order
+/// until the satisfied one is found, so the empty list is never satisfied.
+pub fn any_of<Event, Ctx>(guards: Vec<Box<dyn Guard<Event, Ctx>>>) -> AnyOf<Event, Ctx> {
+    AnyOf {
+        guards,
+        name: OnceCell::new(),
+    }
+}
+
+impl<Event, Ctx> Guard<Event, Ctx> for AnyOf<Event, Ctx> {
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        self.guards.iter().any(|g| g.check(input, ctx))
+    }
+    fn name(&self) -> Option<&str> {
+        self.name
+            .get_or_init(|| join(self.guards.iter().map(|g| g.name()), " || ", "false"))
+            .as_deref()
+    }
+}
+
+/// Guard that is satisfied when all the guards are satisfied, see `all_of`.
+pub struct AllOf<Event, Ctx = ()> {
+    guards: Vec<Box<dyn Guard<Event, Ctx>>>,
+    name: OnceCell<Option<String>>,
+}
+
+/// Combines guards, so that all of them must be satisfied. Guards are checked in order until the
+/// unsatisfied one is found, so the empty list is always satisfied.
+pub fn all_of<Event, Ctx>(guards: Vec<Box<dyn Guard<Event, Ctx>>>) -> AllOf<Event, Ctx> {
+    AllOf {
+        guards,
+        name: OnceCell::new(),
+    }
+}
+
+impl<Event, Ctx> Guard<Event, Ctx> for AllOf<Event, Ctx> {
+    fn check(&self, input: &Event, ctx: &mut Ctx) -> bool {
+        self.guards.iter().all(|g| g.check(input, ctx))
+    }
+    fn name(&self) -> Option<&str> {
+        self.name
+            .get_or_init(|| join(self.guards.iter().map(|g| g.name()), " && ", "true"))
+            .as_deref()
+    }
+}
+
+/// Joins names of the guards with the operator, or returns `empty` if there are no guards. The
+/// combination has no name if any of the guards has none.
+fn join<'a>(
+    names: impl Iterator<Item = Option<&'a str>>,
+    operator: &str,
+    empty: &str,
+) -> Option<String> {
+    let names: Vec<_> = names.collect::<Option<_>>()?;
+    Some(match names.len() {
+        0 => empty.to_string(),
+        1 => names[0].to_string(),
+        _ => format!("({})", names.join(operator)),
+    })
+}
+
+/// Guard of the data of type `S`, that checks data of the vertex, see
+/// `GuardedTransition::guard_state`. Data of other types is rejected.
+struct OfState<S, G>(G, PhantomData<fn(&S)>);
+
+impl<S, G, DynData, Event, Ctx> StateGuard<DynData, Event, Ctx> for OfState<S, G>
+where
+    S: 'static,
+    G: StateGuard<S, Event, Ctx>,
+    DynData: ?Sized + Cast<S>,
+{
+    fn check(&self, state: &DynData, input: &Event, ctx: &mut Ctx) -> bool {
+        DynData::downcast_ref(state).is_some_and(|state| self.0.check(state, input, ctx))
+    }
+    fn name(&self) -> Option<&str> {
+        self.0.name()
+    }
+}
+
+pub struct GuardedTransition<FEvent, Tr, Ctx = (), DynData: ?Sized = dyn Any> {
+    pub(crate) guards: Vec<Box<dyn Guard<FEvent, Ctx>>>,
+    pub(crate) state_guards: Vec<Box<dyn StateGuard<DynData, FEvent, Ctx>>>,
+    pub(crate) transition: Tr,
+}
+
+impl<Event, Ctx, DynData: ?Sized> GuardedTransition<Event, EmptyTransition, Ctx, DynData> {
     pub fn new() -> Self {
         GuardedTransition {
             guards: vec![],
+            state_guards: vec![],
             transition: EmptyTransition,
         }
     }
 
-    pub fn guard<G: Guard<Event> + 'static>(mut self, guard: G) -> Self {
+    pub fn guard<G: Guard<Event, Ctx> + 'static>(mut self, guard: G) -> Self {
         self.guards.push(Box::new(guard));
         self
     }
 
-    pub fn transition<NewTr>(self, transition: NewTr) -> GuardedTransition<Event, NewTr> {
-        let Self { guards, .. } = self;
-        GuardedTransition { guards, transition }
+    pub fn transition<NewTr>(
+        self,
+        transition: NewTr,
+    ) -> GuardedTransition<Event, NewTr, Ctx, DynData> {
+        let Self {
+            guards,
+            state_guards,
+            ..
+        } = self;
+        GuardedTransition {
+            guards,
+            state_guards,
+            transition,
+        }
+    }
+}
+
+impl<FEvent, Tr, Ctx, DynData> GuardedTransition<FEvent, Tr, Ctx, DynData>
+where
+    Tr: TransitionInput,
+    Tr::Input: 'static,
+    DynData: ?Sized + Cast<Tr::Input>,
+{
+    /// Adds the guard that checks data of the source state together with the event. The data is
+    /// of the input type of the transition, so the guard is added after the transition. The
+    /// guard only borrows the data and the event, so when it rejects the transition, both stay
+    /// untouched.
+    pub fn guard_state<G>(mut self, guard: G) -> Self
+    where
+        G: StateGuard<Tr::Input, FEvent, Ctx> + 'static,
+    {
+        self.state_guards
+            .push(Box::new(OfState(guard, PhantomData::<fn(&Tr::Input)>)));
+        self
+    }
+}
+
+impl<FEvent, Tr: TransitionInput, Ctx, DynData: ?Sized> TransitionInput
+    for GuardedTransition<FEvent, Tr, Ctx, DynData>
+{
+    type Input = Tr::Input;
+}
+
+impl<FEvent, Tr: TransitionOutput, Ctx, DynData: ?Sized> TransitionOutput
+    for GuardedTransition<FEvent, Tr, Ctx, DynData>
+{
+    type Output = Tr::Output;
+}
+
+impl<FEvent, Tr: TransitionEvent, Ctx, DynData: ?Sized> TransitionEvent
+    for GuardedTransition<FEvent, Tr, Ctx, DynData>
+{
+    type Event = Tr::Event;
+}
+
+impl<FEvent, Tr, Ctx, DynData: ?Sized> GuardedTransition<FEvent, Tr, Ctx, DynData> {
+    /// Checks all guards without consuming the state data or the event. Returns the error with
+    /// the name of the first guard that is not satisfied, if it has one.
+    fn check_guards(
+        &self,
+        state: &DynData,
+        event: &FEvent,
+        ctx: &mut Ctx,
+    ) -> Result<(), TransitionErrorKind> {
+        let rejected = match self.guards.iter().find(|g| !g.check(event, ctx)) {
+            Some(guard) => Some(guard.name()),
+            None => self
+                .state_guards
+                .iter()
+                .find(|g| !g.check(state, event, ctx))
+                .map(|guard| guard.name()),
+        };
+        match rejected {
+            Some(Some(name)) => Err(TransitionErrorKind::NamedGuardErr(name.to_string())),
+            Some(None) => Err(TransitionErrorKind::GuardErr),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<FEvent, Tr, Ctx, DynData: ?Sized> GuardedTransition<FEvent, Tr, Ctx, DynData> {
+    /// Returns names of the guards of this transition, not of the inner one.
+    fn own_guards(&self) -> Vec<Option<&str>> {
+        let guards = self.guards.iter().map(|g| g.name());
+        guards
+            .chain(self.state_guards.iter().map(|g| g.name()))
+            .collect()
     }
 }
 
-impl<FEvent, Tr, DynData: ?Sized> Transition<DynData> for GuardedTransition<FEvent, Tr>
+impl<Event, Ctx, DynData: ?Sized> Default
+    for GuardedTransition<Event, EmptyTransition, Ctx, DynData>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<FEvent, Tr, DynData, Ctx> Transition<DynData, Ctx>
+    for GuardedTransition<FEvent, Tr, Ctx, DynData>
 where
     FEvent: Any + 'static,
-    Tr: Transition<DynData>,
+    Tr: Transition<DynData, Ctx>,
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
 {
     fn transition(
         &self,
-        from: &mut Vertex<DynData>,
+        from: &mut Vertex<DynData, Ctx>,
         event: Event,
+        ctx: &mut Ctx,
     ) -> Result<TransitionOut<DynData>, TransitionError> {
         let event = event.downcast().map_err(|event| TransitionError {
             event,
             kind: TransitionErrorKind::WrongEvent,
         })?;
-        match self.guards.iter().map(|g| g.check(&event)).all(|x| x) {
-            true => self.transition.transition(from, event),
-            false => Err(TransitionError::new(event, TransitionErrorKind::GuardErr)),
+        match self.check_guards(from.get_data_as_ref(), &event, ctx) {
+            Ok(()) => self.transition.transition(from, event, ctx),
+            Err(kind) => Err(TransitionError::new(event, kind)),
         }
     }
-    fn input_tid(&self) -> TypeId {
-        self.transition.input_tid()
+    fn check(
+        &self,
+        from: &Vertex<DynData, Ctx>,
+        event: &dyn Any,
+        ctx: &mut Ctx,
+    ) -> Result<(), TransitionErrorKind> {
+        let fevent = event
+            .downcast_ref::<FEvent>()
+            .ok_or(TransitionErrorKind::WrongEvent)?;
+        self.check_guards(from.get_data_as_ref(), fevent, ctx)?;
+        self.transition.check(from, event, ctx)
+    }
+    fn perform(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        self.transition.perform(from, event, ctx)
+    }
+    fn input_type(&self) -> TypeInfo {
+        self.transition.input_type()
+    }
+    fn output_types(&self) -> Vec<TypeInfo> {
+        self.transition.output_types()
     }
-    fn output_tid(&self) -> TypeId {
-        self.transition.output_tid()
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+    fn is_guarded(&self) -> bool {
+        !self.guards.is_empty() || !self.state_guards.is_empty() || self.transition.is_guarded()
+    }
+    fn kind(&self) -> TransitionKind {
+        self.transition.kind()
+    }
+    fn guards(&self) -> Vec<Option<&str>> {
+        let mut guards = self.own_guards();
+        guards.extend(self.transition.guards());
+        guards
+    }
+    fn compound(&self) -> Option<Compound<'_>> {
+        let mut compound = self.transition.compound()?;
+        compound.guards.splice(0..0, self.own_guards());
+        Some(compound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::GuardedTransition;
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{EnterSmEvent, SmBuilder, SmError};
+    use std::any::Any;
+
+    #[test]
+    fn test_state_guards() {
+        use crate::guard;
+
+        #[derive(Debug, PartialEq)]
+        struct Counting(u32);
+        #[derive(Debug, PartialEq)]
+        struct Done;
+        #[derive(Debug, PartialEq)]
+        struct Tick(bool);
+
+        let mut sm = SmBuilder::<dyn Any, u32>::new()
+            .register_vertex(SimpleVertex::<Counting, u32>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Done, u32>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Counting(0)))
+            .transition(
+                GuardedTransition::new()
+                    .transition(ftrans(|c: Counting, _: Tick| Counting(c.0 + 1)))
+                    .guard_state(|c: &Counting, _: &Tick| c.0 < 2),
+            )
+            .transition(
+                GuardedTransition::new()
+                    .transition(ftrans(|_: Counting, _: Tick| Done))
+                    .guard_state(guard::named("finish", |_: &Counting, t: &Tick| t.0))
+                    .guard_state(guard::with_ctx(
+                        |c: &Counting, _: &Tick, limit: &mut u32| c.0 >= *limit,
+                    )),
+            )
+            .build_with_ctx(2)
+            .unwrap();
+
+        assert_eq!(sm.process(Tick(false)), Ok(()));
+        assert_eq!(sm.process(Tick(true)), Ok(()));
+        assert_eq!(
+            sm.process(Tick(false)),
+            Err(SmError::GuardRejected {
+                event: Tick(false),
+                guard: "finish".to_string()
+            })
+        );
+        assert_eq!(sm.current_state_concrete::<Counting>(), Some(&Counting(2)));
+        let scxml = sm.to_scxml();
+        let unnamed = "umlsm.guard.tests.test_state_guards.Counting.\
+                       umlsm.guard.tests.test_state_guards.Tick.guard";
+        assert!(scxml.contains(&format!("cond=\"{}1\"", unnamed)));
+        assert!(scxml.contains(&format!("cond=\"finish &amp;&amp; {}2\"", unnamed)));
+
+        assert_eq!(sm.process(Tick(true)), Ok(()));
+        assert!(sm.is_finished());
+    }
+
+    #[test]
+    fn test_guard_combinators() {
+        use crate::guard::{self, all_of, and, any_of, named, not, or, Guard};
+        use crate::transition::{Transition, TransitionErrorKind};
+        use crate::vertex::StateTrait;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Busy;
+        #[derive(Debug, PartialEq)]
+        struct Job(u32);
+
+        let small = || named("small", |job: &Job| job.0 < 10);
+        let even = || named("even", |job: &Job| job.0 % 2 == 0);
+        let guard = or(
+            and(small(), not(even())),
+            named("big", |job: &Job| job.0 > 100),
+        );
+        assert_eq!(
+            Guard::<Job>::name(&guard),
+            Some("((small && !even) || big)")
+        );
+        assert!(guard.check(&Job(3), &mut ()));
+        assert!(!guard.check(&Job(4), &mut ()));
+        assert!(guard.check(&Job(200), &mut ()));
+
+        let any = any_of::<Job, ()>(vec![Box::new(small()), Box::new(even())]);
+        assert_eq!(any.name(), Some("(small || even)"));
+        assert_eq!(Guard::<Job>::name(&not(|_: &Job| true)), None);
+        assert!(any.check(&Job(12), &mut ()));
+        assert!(!any.check(&Job(13), &mut ()));
+        let all = all_of::<Job, u32>(vec![
+            Box::new(small()),
+            Box::new(guard::with_ctx(|job: &Job, max: &mut u32| job.0 <= *max)),
+        ]);
+        assert!(all.check(&Job(5), &mut 5));
+        assert!(!all.check(&Job(5), &mut 4));
+        assert!(all_of::<Job, ()>(vec![]).check(&Job(50), &mut ()));
+        assert!(!any_of::<Job, ()>(vec![]).check(&Job(50), &mut ()));
+
+        let transition = GuardedTransition::new()
+            .guard(small())
+            .guard(not(even()))
+            .transition(ftrans(|_: Idle, _: Job| Busy));
+        let mut vertex = SimpleVertex::with_data(Idle).to_vertex::<dyn Any>();
+        let error = transition
+            .transition(&mut vertex, Box::new(Job(4)), &mut ())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.kind,
+            TransitionErrorKind::NamedGuardErr("!even".to_string())
+        );
+        assert_eq!(error.event.downcast_ref(), Some(&Job(4)));
+        assert_eq!(vertex.get_data_as_ref().downcast_ref(), Some(&Idle));
+
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Busy>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(transition)
+            .build()
+            .unwrap();
+        assert!(sm.to_scxml().contains("cond=\"small &amp;&amp; !even\""));
+        assert_eq!(
+            sm.process(Job(4)),
+            Err(SmError::GuardRejected {
+                event: Job(4),
+                guard: "!even".to_string()
+            })
+        );
+        assert_eq!(sm.process(Job(5)), Ok(()));
+        assert!(sm.is_finished());
     }
 }
@@ -0,0 +1,572 @@
+//! Junction and choice pseudo-states.
+//!
+//! Both are compound transitions: the incoming segment leads from the source state to the
+//! pseudo-state, then one of the outgoing segments leads from the pseudo-state to the target
+//! state. Outgoing segments are transitions triggered by `EnterSmEvent`, each of them is added
+//! with the guard of its branch, and the first segment whose guard is satisfied is used,
+//! otherwise the `else` segment is used. Several conditions of the branch are combined with
+//! `guard::and` or `guard::all_of`.
+//!
+//! The incoming segment must have the only output, whose type is the data type of the
+//! pseudo-state, see `TransitionOutput`. So it is a `Switch`, a function transition or a guarded
+//! one of them, and the data type of the choice is checked by the compiler.
+//!
+//! Junction and choice without `else` segment do not implement `Transition`, so they cannot be
+//! registered in the state machine.
+//!
+//! The selected outgoing segment cannot fail, as the source state is already left then.
+//! `SmBuilder::build` checks that every outgoing segment is an external transition from the
+//! pseudo-state triggered by `EnterSmEvent`, and that it has no guards of its own: only the
+//! guards of the branch select it, and the `else` segment has none.
+
+use crate::event::{EnterSmEvent, Event};
+use crate::guard::Guard;
+use crate::state::Cast;
+use crate::transition::{
+    Transition, TransitionError, TransitionErrorKind, TransitionEvent, TransitionInput,
+    TransitionKind, TransitionOut, TransitionOutput, TypeInfo,
+};
+use crate::vertex::{PseudoState, PseudoStateKind, Vertex};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+/// Marker of the absent `else` segment.
+pub struct NoElse;
+
+/// Junction or choice pseudo-state passed by the compound transition, see
+/// `Transition::compound`.
+pub struct Compound<'a> {
+    pub kind: CompoundKind,
+    /// Type of the pseudo-state data, that is produced by the incoming segment.
+    pub pseudo_state: TypeInfo,
+    /// Names of guards of the incoming segment, see `Transition::guards`.
+    pub guards: Vec<Option<&'a str>>,
+    /// Outgoing segments in the order they are tried, the `else` segment is the last one.
+    pub segments: Vec<Segment<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundKind {
+    Junction,
+    Choice,
+}
+
+/// Outgoing segment of the junction or choice pseudo-state.
+pub struct Segment<'a> {
+    /// Type of data of the vertex the segment starts in, it must be the pseudo-state.
+    pub input: TypeInfo,
+    /// Types of data of all vertexes the segment can end in.
+    pub outputs: Vec<TypeInfo>,
+    /// Type of the event that triggers the segment, it must be `EnterSmEvent`.
+    pub event: TypeInfo,
+    /// How the segment treats the pseudo-state, it must be an external transition.
+    pub kind: TransitionKind,
+    /// Names of guards of the segment, see `Transition::guards`.
+    pub guards: Vec<Option<&'a str>>,
+    /// `true` if the transition of the segment has guards of its own, apart from the guards of
+    /// the branch that select it.
+    pub guarded: bool,
+}
+
+type Branch<Input, DynData, Ctx> = (
+    Box<dyn Guard<Input, Ctx>>,
+    Box<dyn Transition<DynData, Ctx>>,
+);
+
+/// Static conditional branch. Guards of the outgoing segments check the event, and they are
+/// evaluated before the incoming segment is performed.
+pub struct Junction<FEvent, In, Else, DynData: ?Sized, Ctx = ()> {
+    incoming: In,
+    branches: Vec<Branch<FEvent, DynData, Ctx>>,
+    otherwise: Else,
+}
+
+impl<FEvent, In, Else, DynData: ?Sized, Ctx> TransitionInput
+    for Junction<FEvent, In, Else, DynData, Ctx>
+where
+    In: TransitionInput,
+{
+    type Input = In::Input;
+}
+
+impl<FEvent, In, Else, DynData: ?Sized, Ctx> TransitionEvent
+    for Junction<FEvent, In, Else, DynData, Ctx>
+where
+    In: TransitionEvent,
+{
+    type Event = In::Event;
+}
+
+impl<FEvent, In, DynData: ?Sized, Ctx> Junction<FEvent, In, NoElse, DynData, Ctx>
+where
+    In: TransitionOutput,
+{
+    /// Makes the junction, whose pseudo-state holds the output data of the incoming segment.
+    pub fn new(incoming: In) -> Self {
+        Junction {
+            incoming,
+            branches: vec![],
+            otherwise: NoElse,
+        }
+    }
+}
+
+impl<FEvent, In, Else, DynData: ?Sized, Ctx> Junction<FEvent, In, Else, DynData, Ctx> {
+    /// Adds the outgoing segment, that is used if `guard` accepts the event.
+    pub fn branch<G, Tr>(mut self, guard: G, segment: Tr) -> Self
+    where
+        G: Guard<FEvent, Ctx> + 'static,
+        Tr: Transition<DynData, Ctx> + 'static,
+    {
+        self.branches.push((Box::new(guard), Box::new(segment)));
+        self
+    }
+
+    pub fn otherwise<NewElse>(
+        self,
+        otherwise: NewElse,
+    ) -> Junction<FEvent, In, NewElse, DynData, Ctx> {
+        let Self {
+            incoming, branches, ..
+        } = self;
+        Junction {
+            incoming,
+            branches,
+            otherwise,
+        }
+    }
+}
+
+impl<FEvent, In, Else, DynData, Ctx> Transition<DynData, Ctx>
+    for Junction<FEvent, In, Else, DynData, Ctx>
+where
+    FEvent: Any + 'static,
+    In: Transition<DynData, Ctx> + TransitionOutput,
+    In::Output: 'static,
+    Else: Transition<DynData, Ctx>,
+    DynData: ?Sized + 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        // Guards of the incoming segment are checked first, then the ones of the branches, and
+        // only then the incoming segment is performed.
+        let branch = match event.downcast_ref::<FEvent>() {
+            Some(fevent) => match self.incoming.check(from, fevent, ctx) {
+                Ok(()) => select(&self.branches, &self.otherwise, fevent, ctx),
+                Err(kind) => return Err(TransitionError::new(event, kind)),
+            },
+            None => return Err(TransitionError::new(event, TransitionErrorKind::WrongEvent)),
+        };
+        let state = match self.incoming.perform(from, event, ctx)? {
+            TransitionOut::Target { state } => state,
+            // The incoming segment is an internal transition, the pseudo-state is not reached.
+            TransitionOut::Stay => return Ok(TransitionOut::Stay),
+        };
+        pass(
+            branch,
+            state,
+            TypeInfo::of::<In::Output>(),
+            PseudoStateKind::Junction,
+            ctx,
+        )
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        self.incoming.input_type()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        outputs(&self.branches, &self.otherwise)
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        self.incoming.event_type()
+    }
+
+    fn is_guarded(&self) -> bool {
+        !self.branches.is_empty() || self.incoming.is_guarded()
+    }
+
+    fn compound(&self) -> Option<Compound<'_>> {
+        Some(compound(
+            CompoundKind::Junction,
+            TypeInfo::of::<In::Output>(),
+            &self.incoming,
+            &self.branches,
+            &self.otherwise,
+        ))
+    }
+}
+
+/// Dynamic conditional branch. Guards of the outgoing segments check the data produced by the
+/// incoming segment, so they are evaluated after the incoming segment is performed.
+pub struct Choice<FData, In, Else, DynData: ?Sized, Ctx = ()> {
+    incoming: In,
+    branches: Vec<Branch<FData, DynData, Ctx>>,
+    otherwise: Else,
+}
+
+impl<FData, In, Else, DynData: ?Sized, Ctx> TransitionInput
+    for Choice<FData, In, Else, DynData, Ctx>
+where
+    In: TransitionInput,
+{
+    type Input = In::Input;
+}
+
+impl<FData, In, Else, DynData: ?Sized, Ctx> TransitionEvent
+    for Choice<FData, In, Else, DynData, Ctx>
+where
+    In: TransitionEvent,
+{
+    type Event = In::Event;
+}
+
+impl<FData, In, DynData: ?Sized, Ctx> Choice<FData, In, NoElse, DynData, Ctx>
+where
+    In: TransitionOutput<Output = FData>,
+{
+    /// Makes the choice, whose pseudo-state holds the output data of the incoming segment.
+    pub fn new(incoming: In) -> Self {
+        Choice {
+            incoming,
+            branches: vec![],
+            otherwise: NoElse,
+        }
+    }
+}
+
+impl<FData, In, Else, DynData: ?Sized, Ctx> Choice<FData, In, Else, DynData, Ctx> {
+    /// Adds the outgoing segment, that is used if `guard` accepts the data of the pseudo-state.
+    pub fn branch<G, Tr>(mut self, guard: G, segment: Tr) -> Self
+    where
+        G: Guard<FData, Ctx> + 'static,
+        Tr: Transition<DynData, Ctx> + 'static,
+    {
+        self.branches.push((Box::new(guard), Box::new(segment)));
+        self
+    }
+
+    pub fn otherwise<NewElse>(
+        self,
+        otherwise: NewElse,
+    ) -> Choice<FData, In, NewElse, DynData, Ctx> {
+        let Self {
+            incoming, branches, ..
+        } = self;
+        Choice {
+            incoming,
+            branches,
+            otherwise,
+        }
+    }
+}
+
+impl<FData, In, Else, DynData, Ctx> Transition<DynData, Ctx>
+    for Choice<FData, In, Else, DynData, Ctx>
+where
+    FData: 'static,
+    In: Transition<DynData, Ctx> + TransitionOutput<Output = FData>,
+    Else: Transition<DynData, Ctx>,
+    DynData: ?Sized + Cast<FData> + 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        let state = match self.incoming.transition(from, event, ctx)? {
+            TransitionOut::Target { state } => state,
+            // The incoming segment is an internal transition, the pseudo-state is not reached.
+            TransitionOut::Stay => return Ok(TransitionOut::Stay),
+        };
+        let data = DynData::downcast_ref(state.as_ref())
+            .expect("Incoming segment produces data of the choice pseudo-state.");
+        let branch = select(&self.branches, &self.otherwise, data, ctx);
+        pass(
+            branch,
+            state,
+            TypeInfo::of::<FData>(),
+            PseudoStateKind::Choice,
+            ctx,
+        )
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        self.incoming.input_type()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        outputs(&self.branches, &self.otherwise)
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        self.incoming.event_type()
+    }
+
+    fn is_guarded(&self) -> bool {
+        !self.branches.is_empty() || self.incoming.is_guarded()
+    }
+
+    fn compound(&self) -> Option<Compound<'_>> {
+        Some(compound(
+            CompoundKind::Choice,
+            TypeInfo::of::<FData>(),
+            &self.incoming,
+            &self.branches,
+            &self.otherwise,
+        ))
+    }
+}
+
+fn select<'a, Input, DynData: ?Sized, Ctx>(
+    branches: &'a [Branch<Input, DynData, Ctx>],
+    otherwise: &'a dyn Transition<DynData, Ctx>,
+    input: &Input,
+    ctx: &mut Ctx,
+) -> &'a dyn Transition<DynData, Ctx> {
+    branches
+        .iter()
+        .find(|(guard, _)| guard.check(input, ctx))
+        .map(|(_, transition)| transition.as_ref())
+        .unwrap_or(otherwise)
+}
+
+fn outputs<Input, DynData: ?Sized, Ctx>(
+    branches: &[Branch<Input, DynData, Ctx>],
+    otherwise: &dyn Transition<DynData, Ctx>,
+) -> Vec<TypeInfo> {
+    branches
+        .iter()
+        .flat_map(|(_, transition)| transition.output_types())
+        .chain(otherwise.output_types())
+        .collect()
+}
+
+fn compound<'a, Input, DynData: ?Sized, Ctx>(
+    kind: CompoundKind,
+    pseudo_state: TypeInfo,
+    incoming: &'a dyn Transition<DynData, Ctx>,
+    branches: &'a [Branch<Input, DynData, Ctx>],
+    otherwise: &'a dyn Transition<DynData, Ctx>,
+) -> Compound<'a> {
+    let segments = branches
+        .iter()
+        .map(|(guard, transition)| Segment {
+            guards: core::iter::once(guard.name())
+                .chain(transition.guards())
+                .collect(),
+            ..segment(transition.as_ref())
+        })
+        .chain(core::iter::once(Segment {
+            guards: otherwise.guards(),
+            ..segment(otherwise)
+        }))
+        .collect();
+    Compound {
+        kind,
+        pseudo_state,
+        guards: incoming.guards(),
+        segments,
+    }
+}
+
+fn segment<DynData: ?Sized, Ctx>(transition: &dyn Transition<DynData, Ctx>) -> Segment<'_> {
+    Segment {
+        input: transition.input_type(),
+        outputs: transition.output_types(),
+        event: transition.event_type(),
+        kind: transition.kind(),
+        guards: vec![],
+        guarded: transition.is_guarded(),
+    }
+}
+
+/// Performs the outgoing segment from the pseudo-state that holds `data`.
+fn pass<DynData: ?Sized + 'static, Ctx>(
+    branch: &dyn Transition<DynData, Ctx>,
+    data: Box<DynData>,
+    data_type: TypeInfo,
+    kind: PseudoStateKind<Ctx>,
+    ctx: &mut Ctx,
+) -> Result<TransitionOut<DynData>, TransitionError> {
+    let mut vertex = Vertex::PseudoState(PseudoState {
+        data: Some(data),
+        data_tid: data_type.tid,
+        data_type_name: data_type.name,
+        kind,
+    });
+    match branch.transition(&mut vertex, Box::new(EnterSmEvent), ctx) {
+        Ok(out) => Ok(out),
+        Err(_) => unreachable!("It should be checked by `SmBuilder::build`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::GuardedTransition;
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{EnterSmEvent, SmBuilder, SmError};
+    use std::any::Any;
+
+    #[test]
+    fn test_junction_and_choice() {
+        use crate::junction::{Choice, Junction};
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Doubled(u32);
+        #[derive(Debug, PartialEq)]
+        struct Big(u32);
+        #[derive(Debug, PartialEq)]
+        struct Small(u32);
+
+        let make_machine = |dynamic: bool| {
+            let builder = SmBuilder::<dyn Any>::new()
+                .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+                .register_vertex(SimpleVertex::<Big>::new().to_final_vertex())
+                .register_vertex(SimpleVertex::<Small>::new().to_final_vertex())
+                .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle));
+            let incoming = ftrans(|_: Idle, n: u32| Doubled(n * 2));
+            let big = ftrans(|d: Doubled, _: EnterSmEvent| Big(d.0));
+            let small = ftrans(|d: Doubled, _: EnterSmEvent| Small(d.0));
+            match dynamic {
+                true => builder.transition(
+                    Choice::new(incoming)
+                        .branch(|d: &Doubled| d.0 > 10, big)
+                        .otherwise(small),
+                ),
+                false => builder.transition(
+                    Junction::new(incoming)
+                        .branch(|n: &u32| *n > 10, big)
+                        .otherwise(small),
+                ),
+            }
+            .build()
+            .unwrap()
+        };
+
+        let mut sm = make_machine(false);
+        assert_eq!(sm.process(6_u32), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Small(12)));
+        let mut sm = make_machine(false);
+        assert_eq!(
+            sm.process("wrong event"),
+            Err(SmError::NoTransitionSatisfyingEvent("wrong event"))
+        );
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+
+        let mut sm = make_machine(true);
+        assert_eq!(sm.process(6_u32), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Big(12)));
+        let mut sm = make_machine(true);
+        assert_eq!(sm.process(5_u32), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Small(10)));
+    }
+
+    #[test]
+    fn test_junction_guard_order() {
+        use crate::guard::with_ctx;
+        use crate::junction::Junction;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        struct Doubled;
+        #[derive(Debug, PartialEq)]
+        struct Done;
+
+        type Log = Vec<&'static str>;
+
+        let mut sm = SmBuilder::<dyn Any, Log>::new()
+            .register_vertex(SimpleVertex::<Idle, Log>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Done, Log>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(
+                Junction::new(
+                    GuardedTransition::new()
+                        .guard(with_ctx(|n: &u32, log: &mut Log| {
+                            log.push("incoming guard");
+                            *n > 0
+                        }))
+                        .transition(ftrans(|_: Idle, _: u32, log: &mut Log| {
+                            log.push("incoming action");
+                            Doubled
+                        })),
+                )
+                .branch(
+                    with_ctx(|_: &u32, log: &mut Log| {
+                        log.push("branch guard");
+                        true
+                    }),
+                    ftrans(|_: Doubled, _: EnterSmEvent| Done),
+                )
+                .otherwise(ftrans(|_: Doubled, _: EnterSmEvent| Done)),
+            )
+            .build()
+            .unwrap();
+
+        // Branch guards are not checked when the incoming segment is rejected.
+        assert_eq!(
+            sm.process(0_u32),
+            Err(SmError::NoTransitionSatisfyingEvent(0))
+        );
+        assert_eq!(sm.ctx().unwrap(), &vec!["incoming guard"]);
+        assert_eq!(sm.process(1_u32), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Done));
+        assert_eq!(
+            sm.ctx().unwrap(),
+            &vec![
+                "incoming guard",
+                "incoming guard",
+                "branch guard",
+                "incoming action"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_segment() {
+        use crate::junction::Junction;
+        use crate::BuildError;
+        use std::any::type_name;
+
+        struct Idle;
+        struct Doubled;
+        struct Done;
+
+        let errors = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(
+                Junction::new(ftrans(|_: Idle, _: u32| Doubled))
+                    .branch(|n: &u32| *n > 10, ftrans(|_: Idle, _: EnterSmEvent| Done))
+                    .branch(|n: &u32| *n > 5, ftrans(|_: Doubled, _: u8| Done))
+                    .otherwise(
+                        GuardedTransition::new()
+                            .guard(|_: &EnterSmEvent| false)
+                            .transition(ftrans(|_: Doubled, _: EnterSmEvent| Done)),
+                    ),
+            )
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                BuildError::WrongSegmentInput(type_name::<Doubled>(), type_name::<Idle>()),
+                BuildError::WrongSegmentEvent(type_name::<Doubled>(), type_name::<u8>()),
+                BuildError::GuardedSegment(type_name::<Doubled>()),
+            ]
+        );
+    }
+}
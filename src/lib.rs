@@ -1,10 +1,33 @@
-pub use event::{EnterSmEvent, Event};
-pub use sm::{Sm, SmBuilder, SmError};
+// Tests link `std` even without the `std` feature, the library itself uses only `alloc` then.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub use async_sm::{AsyncSm, AsyncSmBuilder};
+pub use event::{CompletionEvent, EnterSmEvent, Event, EventOf, EventQueue, SmEvent};
+pub use sm::{Analysis, BuildError, Sm, SmBuilder, SmError};
+pub use umlsm_macros::{state_machine, SmEvent};
 pub use vertex::Vertex;
 
+// Makes `::umlsm` paths generated by `state_machine!` work inside this crate.
+extern crate self as umlsm;
+
+/// Items used by the code generated by `state_machine!`, so that it works without `std`.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::boxed::Box;
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
+pub mod async_sm;
 mod event;
+mod export;
 pub mod guard;
+pub mod junction;
 mod macros;
+#[cfg(feature = "scxml")]
+pub mod scxml;
 mod sm;
 pub mod state;
 pub mod transition;
@@ -24,9 +47,9 @@ mod tests {
         struct SomeState2;
 
         let mut machine = SmBuilder::<dyn Any>::with_default_state(SomeState)
-            .transition(ftrans(|_: SomeState, event: EnterSmEvent| SomeState))
-            .register_vertex(SimpleVertex::<SomeState2>::new().to_vertex())
-            .transition(ftrans(|_: SomeState, event: i32| SomeState2))
+            .transition(ftrans(|_: SomeState, _: EnterSmEvent| SomeState))
+            .register_vertex(SimpleVertex::<SomeState2>::new().to_final_vertex())
+            .transition(ftrans(|_: SomeState, _: i32| SomeState2))
             .build()
             .unwrap();
 
@@ -49,8 +72,8 @@ mod tests {
         let make_machine = || {
             SmBuilder::<dyn Any>::new()
                 .register_vertex(SimpleVertex::with_data(ChooseState).to_vertex())
-                .register_vertex(SimpleVertex::<DivisibleBy2>::new().to_vertex())
-                .register_vertex(SimpleVertex::<DivisibleBy3>::new().to_vertex())
+                .register_vertex(SimpleVertex::<DivisibleBy2>::new().to_final_vertex())
+                .register_vertex(SimpleVertex::<DivisibleBy3>::new().to_final_vertex())
                 .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| ChooseState))
                 .transition(
                     GuardedTransition::new()
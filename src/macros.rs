@@ -39,18 +39,18 @@ macro_rules! states {
         }
     ) => {
         // Trait that is used as `dyn DynState` in the umlsm::Sm<...> generic.
-        $v trait $dyn_state: std::any::Any + $( $($trait1 $( :: $trait2 )* $( < $($gen)* > )? +)+ )? {
-            fn tid(&self) -> std::any::TypeId;
+        $v trait $dyn_state: core::any::Any + $( $($trait1 $( :: $trait2 )* $( < $($gen)* > )? +)+ )? {
+            fn tid(&self) -> core::any::TypeId;
         }
 
         impl<T: 'static + $( $( $trait1 $( :: $trait2 )* $( < $($gen)* > )? +)+ )?> $dyn_state for T {
             // This method needed to recognize TypeId of a `T` type.
-            fn tid(&self) -> std::any::TypeId {
-                std::any::TypeId::of::<T>()
+            fn tid(&self) -> core::any::TypeId {
+                core::any::TypeId::of::<T>()
             }
         }
 
-        impl<T: std::any::Any + $( $( $trait1 $( :: $trait2 )* $( < $($gen)* > )? +)+ )?> $crate::state::Cast<T> for dyn $dyn_state {
+        impl<T: core::any::Any + $( $( $trait1 $( :: $trait2 )* $( < $($gen)* > )? +)+ )?> $crate::state::Cast<T> for dyn $dyn_state {
             fn upcast(from: Box<T>) -> Box<Self> {
                 from
             }
@@ -59,7 +59,7 @@ macro_rules! states {
                 from
             }
 
-            fn concrete_tid(&self) -> std::any::TypeId {
+            fn concrete_tid(&self) -> core::any::TypeId {
                 self.tid()
             }
         }
@@ -84,14 +84,14 @@ macro_rules! switch {
             $trait1 $( :: $trait2 )* $( < $($gen),* > )?,
             $event,
             _,
+            _,
         >::new($output)
     };
 }
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod compile_tests {
-    use super::*;
-
     events! {
         #[derive(Debug, PartialEq)]
         #[repr(C)]
@@ -110,7 +110,7 @@ mod compile_tests {
     }
 
     states! {
-        pub trait DynState2: std::fmt::Debug;
+        pub trait DynState2: core::fmt::Debug;
         {
             #[derive(Debug)]
             struct State2;
@@ -118,7 +118,7 @@ mod compile_tests {
     }
 
     states! {
-        pub trait DynState3: std::fmt::Debug, std::fmt::Display;
+        pub trait DynState3: core::fmt::Debug, core::fmt::Display;
         {
             struct State3;
         }
@@ -0,0 +1,688 @@
+//! Import of state machines from [SCXML](https://www.w3.org/TR/scxml/) documents.
+//!
+//! SCXML describes only the topology of the state machine, so the caller provides a
+//! [`Registry`] that maps identifiers used in the document to Rust types:
+//! - `id` of every `<state>`, `<parallel>` and `<final>` to the constructor of the state data;
+//! - every name in the `event` attribute of `<transition>` to the event type;
+//! - the `cond` attribute of `<transition>` to the guard.
+//!
+//! By default the transition drops the data of the source state and enters the target state
+//! with the newly constructed data. Transitions with custom actions can be registered with
+//! [`Registry::transition`].
+//!
+//! Supported elements are `<scxml>`, `<state>`, `<parallel>`, `<final>`, `<initial>`,
+//! `<history>` and `<transition>`. A `<state>` with child states becomes a composite state, a
+//! `<parallel>` becomes an orthogonal state with a region per child state. Transitions must
+//! target sibling states or a history pseudo-state of a sibling composite state, which is the
+//! same as targeting the composite state. Executable content and the data model are not
+//! supported.
+
+use crate::event::{EnterSmEvent, Event};
+use crate::guard::Guard;
+use crate::state::{
+    Cast, DeepHistoryPseudoState, InitialPseudoState, OrthogonalVertex, ShallowHistoryPseudoState,
+    SimpleVertex,
+};
+use crate::transition::{
+    Transition, TransitionError, TransitionErrorKind, TransitionOut, TypeInfo,
+};
+use crate::vertex::StateTrait;
+use crate::{SmBuilder, Vertex};
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use core::any::Any;
+
+type Machine<DynData, Ctx> = Box<dyn Fn() -> SmBuilder<DynData, Ctx>>;
+
+/// Shape of the vertex described by the SCXML element.
+enum Shape<DynData: ?Sized, Ctx> {
+    Atomic,
+    Final,
+    Composite(Machine<DynData, Ctx>),
+    Parallel(Vec<Machine<DynData, Ctx>>),
+}
+
+type VertexFactory<DynData, Ctx> = Box<dyn Fn(Shape<DynData, Ctx>) -> Vertex<DynData, Ctx>>;
+
+struct StateEntry<DynData: ?Sized, Ctx> {
+    info: TypeInfo,
+    data: Rc<dyn Fn() -> Box<DynData>>,
+    vertex: VertexFactory<DynData, Ctx>,
+}
+
+type Check<Ctx> = Rc<dyn Fn(&dyn Any, &mut Ctx) -> bool>;
+
+struct GuardEntry<Ctx> {
+    event: TypeInfo,
+    check: Check<Ctx>,
+}
+
+type TransitionFactory<DynData, Ctx> = Box<dyn Fn() -> Box<dyn Transition<DynData, Ctx>>>;
+
+/// Maps identifiers used in the SCXML document to Rust types. `Ctx` is the context of the
+/// loaded state machine, that is passed to guards, transitions and actions of states.
+pub struct Registry<DynData: ?Sized = dyn Any, Ctx = ()> {
+    states: BTreeMap<String, StateEntry<DynData, Ctx>>,
+    events: BTreeMap<String, TypeInfo>,
+    guards: BTreeMap<String, GuardEntry<Ctx>>,
+    transitions: BTreeMap<(String, String), TransitionFactory<DynData, Ctx>>,
+}
+
+impl<DynData: ?Sized + 'static, Ctx: 'static> Default for Registry<DynData, Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<DynData: ?Sized + 'static, Ctx: 'static> Registry<DynData, Ctx> {
+    pub fn new() -> Self {
+        Registry {
+            states: BTreeMap::new(),
+            events: BTreeMap::new(),
+            guards: BTreeMap::new(),
+            transitions: BTreeMap::new(),
+        }
+    }
+
+    /// Registers the state with the `id`. `data` constructs the state data every time the
+    /// state is entered.
+    pub fn state<T: 'static>(self, id: &str, data: impl Fn() -> T + 'static) -> Self
+    where
+        DynData: Cast<T>,
+    {
+        self.state_vertex(id, data, SimpleVertex::new)
+    }
+
+    /// The same as `state`, but `vertex` describes the vertex, e.g. with entry and exit
+    /// actions.
+    pub fn state_vertex<T: 'static>(
+        mut self,
+        id: &str,
+        data: impl Fn() -> T + 'static,
+        vertex: impl Fn() -> SimpleVertex<T, Ctx> + 'static,
+    ) -> Self
+    where
+        DynData: Cast<T>,
+    {
+        let entry = StateEntry {
+            info: TypeInfo::of::<T>(),
+            data: Rc::new(move || DynData::upcast(Box::new(data()))),
+            vertex: Box::new(move |shape| match shape {
+                Shape::Atomic => vertex().to_vertex(),
+                Shape::Final => vertex().to_final_vertex(),
+                Shape::Composite(machine) => vertex().to_submachine_vertex(machine),
+                Shape::Parallel(regions) => regions
+                    .into_iter()
+                    .fold(OrthogonalVertex::new(vertex()), |o, r| o.region(r))
+                    .to_vertex(),
+            }),
+        };
+        self.states.insert(id.to_string(), entry);
+        self
+    }
+
+    /// Registers the event type with the `id`.
+    pub fn event<E: Any>(mut self, id: &str) -> Self {
+        self.events.insert(id.to_string(), TypeInfo::of::<E>());
+        self
+    }
+
+    /// Registers the guard with the `id`. The guard can be used only in transitions triggered
+    /// by events of type `E`.
+    pub fn guard<E: Any>(mut self, id: &str, guard: impl Guard<E, Ctx> + 'static) -> Self {
+        let check = move |event: &dyn Any, ctx: &mut Ctx| {
+            event
+                .downcast_ref::<E>()
+                .is_some_and(|e| guard.check(e, ctx))
+        };
+        self.guards.insert(
+            id.to_string(),
+            GuardEntry {
+                event: TypeInfo::of::<E>(),
+                check: Rc::new(check),
+            },
+        );
+        self
+    }
+
+    /// Registers the transition that is used instead of the default one for the transition from
+    /// the state `source` on the event `event`. The transition must end in the target state of
+    /// the SCXML transition. The guard from the document is still checked before it.
+    pub fn transition<T>(
+        mut self,
+        source: &str,
+        event: &str,
+        transition: impl Fn() -> T + 'static,
+    ) -> Self
+    where
+        T: Transition<DynData, Ctx> + 'static,
+    {
+        self.transitions.insert(
+            (source.to_string(), event.to_string()),
+            Box::new(move || Box::new(transition())),
+        );
+        self
+    }
+}
+
+/// Problem found while loading the SCXML document.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ScxmlError {
+    /// The document is not a well-formed XML.
+    Xml(String),
+    /// The root element is not `<scxml>`.
+    NotScxml,
+    /// The element is not supported.
+    UnsupportedElement(String),
+    /// The required attribute of the element is absent.
+    MissingAttribute {
+        element: String,
+        attribute: &'static str,
+    },
+    /// The state is not registered in the registry.
+    UnknownState(String),
+    /// The event is not registered in the registry.
+    UnknownEvent(String),
+    /// The guard is not registered in the registry.
+    UnknownGuard(String),
+    /// The guard is registered for the other event type.
+    GuardEventMismatch { guard: String, event: String },
+    /// The transition target is not a sibling state or a history pseudo-state of the sibling
+    /// composite state.
+    UnsupportedTarget(String),
+    /// The transition has no event and it is not a default transition of the initial or history
+    /// pseudo-state.
+    EventlessTransition(String),
+}
+
+/// Loads the state machine from the SCXML document.
+pub fn load<DynData, Ctx>(
+    document: &str,
+    registry: Registry<DynData, Ctx>,
+) -> Result<SmBuilder<DynData, Ctx>, ScxmlError>
+where
+    DynData: Cast<InitialPseudoState>
+        + Cast<ShallowHistoryPseudoState>
+        + Cast<DeepHistoryPseudoState>
+        + ?Sized
+        + 'static,
+    Ctx: 'static,
+{
+    let document =
+        roxmltree::Document::parse(document).map_err(|e| ScxmlError::Xml(e.to_string()))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "scxml" {
+        return Err(ScxmlError::NotScxml);
+    }
+    let machine = Rc::new(parse_machine(root)?);
+    let registry = Rc::new(registry);
+    builder(&machine, &registry)
+}
+
+/// State machine described by `<scxml>` or by a compound `<state>`.
+struct MachineDef {
+    initial: Option<String>,
+    states: Vec<StateDef>,
+}
+
+struct StateDef {
+    id: String,
+    kind: StateKind,
+    transitions: Vec<TransitionDef>,
+}
+
+enum StateKind {
+    Atomic,
+    Final,
+    Compound(Rc<MachineDef>),
+    Parallel(Vec<Rc<MachineDef>>),
+    History { deep: bool },
+}
+
+struct TransitionDef {
+    events: Vec<String>,
+    cond: Option<String>,
+    target: String,
+}
+
+fn parse_machine(node: roxmltree::Node) -> Result<MachineDef, ScxmlError> {
+    let mut initial = node.attribute("initial").map(str::to_string);
+    let mut states = vec![];
+    for child in node.children().filter(|n| n.is_element()) {
+        match child.tag_name().name() {
+            "initial" => {
+                let transition = child
+                    .children()
+                    .find(|n| n.is_element())
+                    .ok_or_else(|| missing(child, "transition"))?;
+                initial = Some(parse_transition(transition)?.target);
+            }
+            "state" | "parallel" | "final" | "history" => states.push(parse_state(child)?),
+            "transition" => {}
+            other => return Err(ScxmlError::UnsupportedElement(other.to_string())),
+        }
+    }
+    Ok(MachineDef { initial, states })
+}
+
+fn parse_state(node: roxmltree::Node) -> Result<StateDef, ScxmlError> {
+    let id = node
+        .attribute("id")
+        .ok_or_else(|| missing(node, "id"))?
+        .to_string();
+    let has_children = node
+        .children()
+        .any(|n| ["state", "parallel", "final"].contains(&n.tag_name().name()));
+    let kind = match node.tag_name().name() {
+        "final" => StateKind::Final,
+        "history" => StateKind::History {
+            deep: node.attribute("type") == Some("deep"),
+        },
+        "parallel" => StateKind::Parallel(
+            node.children()
+                .filter(|n| n.is_element() && n.tag_name().name() != "transition")
+                .map(|region| {
+                    let state = parse_state(region)?;
+                    Ok(Rc::new(MachineDef {
+                        initial: Some(state.id.clone()),
+                        states: vec![state],
+                    }))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        _ if has_children => StateKind::Compound(Rc::new(parse_machine(node)?)),
+        _ => StateKind::Atomic,
+    };
+    let mut transitions = vec![];
+    for child in node.children().filter(|n| n.is_element()) {
+        match child.tag_name().name() {
+            "transition" => transitions.push(parse_transition(child)?),
+            "state" | "parallel" | "final" | "history" | "initial" if has_children => {}
+            other => return Err(ScxmlError::UnsupportedElement(other.to_string())),
+        }
+    }
+    Ok(StateDef {
+        id,
+        kind,
+        transitions,
+    })
+}
+
+fn parse_transition(node: roxmltree::Node) -> Result<TransitionDef, ScxmlError> {
+    if node.tag_name().name() != "transition" {
+        return Err(ScxmlError::UnsupportedElement(
+            node.tag_name().name().to_string(),
+        ));
+    }
+    Ok(TransitionDef {
+        events: node
+            .attribute("event")
+            .map(|e| e.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+        cond: node.attribute("cond").map(str::to_string),
+        target: node
+            .attribute("target")
+            .ok_or_else(|| missing(node, "target"))?
+            .to_string(),
+    })
+}
+
+fn missing(node: roxmltree::Node, attribute: &'static str) -> ScxmlError {
+    ScxmlError::MissingAttribute {
+        element: node.tag_name().name().to_string(),
+        attribute,
+    }
+}
+
+/// Describes the state machine. Nested state machines are checked eagerly, so that they can be
+/// described again without errors every time the composite state is entered.
+fn builder<DynData, Ctx>(
+    machine: &Rc<MachineDef>,
+    registry: &Rc<Registry<DynData, Ctx>>,
+) -> Result<SmBuilder<DynData, Ctx>, ScxmlError>
+where
+    DynData: Cast<InitialPseudoState>
+        + Cast<ShallowHistoryPseudoState>
+        + Cast<DeepHistoryPseudoState>
+        + ?Sized
+        + 'static,
+    Ctx: 'static,
+{
+    let nested = |machine: &Rc<MachineDef>| -> Result<Machine<DynData, Ctx>, ScxmlError> {
+        builder(machine, registry)?;
+        let (machine, registry) = (machine.clone(), registry.clone());
+        Ok(Box::new(move || {
+            builder(&machine, &registry).expect("It is checked on load.")
+        }))
+    };
+
+    let mut sm = SmBuilder::new();
+    for state in &machine.states {
+        let vertex = match &state.kind {
+            StateKind::History { deep: false } => Vertex::shallow_history(),
+            StateKind::History { deep: true } => Vertex::deep_history(),
+            kind => {
+                let entry = registry
+                    .states
+                    .get(&state.id)
+                    .ok_or_else(|| ScxmlError::UnknownState(state.id.clone()))?;
+                let shape = match kind {
+                    StateKind::Atomic => Shape::Atomic,
+                    StateKind::Final => Shape::Final,
+                    StateKind::Compound(machine) => Shape::Composite(nested(machine)?),
+                    StateKind::Parallel(regions) => {
+                        Shape::Parallel(regions.iter().map(nested).collect::<Result<_, _>>()?)
+                    }
+                    StateKind::History { .. } => unreachable!(),
+                };
+                (entry.vertex)(shape)
+            }
+        };
+        sm = sm.register_vertex(vertex);
+    }
+
+    let initial = match &machine.initial {
+        Some(initial) => initial.clone(),
+        None => match machine.states.first() {
+            Some(state) => state.id.clone(),
+            None => return Ok(sm),
+        },
+    };
+    sm = sm.transition(default_transition(
+        TypeInfo::of::<InitialPseudoState>(),
+        target(machine, registry, &initial)?,
+    ));
+
+    for state in &machine.states {
+        let input = match state.kind {
+            StateKind::History { deep: false } => TypeInfo::of::<ShallowHistoryPseudoState>(),
+            StateKind::History { deep: true } => TypeInfo::of::<DeepHistoryPseudoState>(),
+            _ => registry.states[&state.id].info,
+        };
+        for def in &state.transitions {
+            let target = target(machine, registry, &def.target)?;
+            if let StateKind::History { .. } = state.kind {
+                sm = sm.transition(default_transition(input, target));
+                continue;
+            }
+            if def.events.is_empty() {
+                return Err(ScxmlError::EventlessTransition(state.id.clone()));
+            }
+            for event_id in &def.events {
+                let event = *registry
+                    .events
+                    .get(event_id)
+                    .ok_or_else(|| ScxmlError::UnknownEvent(event_id.clone()))?;
+                let guard = match &def.cond {
+                    Some(cond) => {
+                        let guard = registry
+                            .guards
+                            .get(cond)
+                            .ok_or_else(|| ScxmlError::UnknownGuard(cond.clone()))?;
+                        if guard.event != event {
+                            return Err(ScxmlError::GuardEventMismatch {
+                                guard: cond.clone(),
+                                event: event_id.clone(),
+                            });
+                        }
+                        Some((cond.clone(), guard.check.clone()))
+                    }
+                    None => None,
+                };
+                let custom = registry
+                    .transitions
+                    .get(&(state.id.clone(), event_id.clone()))
+                    .map(|f| f());
+                sm = sm.transition(ScxmlTransition {
+                    input,
+                    event,
+                    guard,
+                    target: target.clone(),
+                    custom,
+                });
+            }
+        }
+    }
+    Ok(sm)
+}
+
+/// Resolves the target of the transition in the `machine`.
+fn target<DynData: ?Sized, Ctx>(
+    machine: &MachineDef,
+    registry: &Registry<DynData, Ctx>,
+    id: &str,
+) -> Result<Target<DynData>, ScxmlError> {
+    let state = machine
+        .states
+        .iter()
+        .find(|s| {
+            s.id == id
+                || matches!(&s.kind, StateKind::Compound(m) if m.states.iter().any(|h| {
+                    h.id == id && matches!(h.kind, StateKind::History { .. })
+                }))
+        })
+        .ok_or_else(|| ScxmlError::UnsupportedTarget(id.to_string()))?;
+    if let StateKind::History { .. } = state.kind {
+        return Err(ScxmlError::UnsupportedTarget(id.to_string()));
+    }
+    let entry = registry
+        .states
+        .get(&state.id)
+        .ok_or_else(|| ScxmlError::UnknownState(state.id.clone()))?;
+    Ok(Target {
+        info: entry.info,
+        data: entry.data.clone(),
+    })
+}
+
+struct Target<DynData: ?Sized> {
+    info: TypeInfo,
+    data: Rc<dyn Fn() -> Box<DynData>>,
+}
+
+impl<DynData: ?Sized> Clone for Target<DynData> {
+    fn clone(&self) -> Self {
+        Target {
+            info: self.info,
+            data: self.data.clone(),
+        }
+    }
+}
+
+fn default_transition<DynData: ?Sized, Ctx>(
+    input: TypeInfo,
+    target: Target<DynData>,
+) -> ScxmlTransition<DynData, Ctx> {
+    ScxmlTransition {
+        input,
+        event: TypeInfo::of::<EnterSmEvent>(),
+        guard: None,
+        target,
+        custom: None,
+    }
+}
+
+/// Transition described by the SCXML `<transition>` element.
+struct ScxmlTransition<DynData: ?Sized, Ctx> {
+    input: TypeInfo,
+    event: TypeInfo,
+    guard: Option<(String, Check<Ctx>)>,
+    target: Target<DynData>,
+    custom: Option<Box<dyn Transition<DynData, Ctx>>>,
+}
+
+impl<DynData: ?Sized + 'static, Ctx: 'static> Transition<DynData, Ctx>
+    for ScxmlTransition<DynData, Ctx>
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        if event.as_ref().type_id() != self.event.tid {
+            return Err(TransitionError::new(event, TransitionErrorKind::WrongEvent));
+        }
+        if let Some((name, guard)) = &self.guard {
+            if !guard(event.as_ref(), ctx) {
+                let kind = TransitionErrorKind::NamedGuardErr(name.clone());
+                return Err(TransitionError::new(event, kind));
+            }
+        }
+        if let Some(custom) = &self.custom {
+            return custom.transition(from, event, ctx);
+        }
+        from.exit(ctx);
+        from.get_data();
+        Ok(TransitionOut::Target {
+            state: (self.target.data)(),
+        })
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        self.input
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        match &self.custom {
+            Some(custom) => custom.output_types(),
+            None => vec![self.target.info],
+        }
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        self.event
+    }
+
+    fn is_guarded(&self) -> bool {
+        self.guard.is_some() || self.custom.as_ref().is_some_and(|c| c.is_guarded())
+    }
+
+    fn guards(&self) -> Vec<Option<&str>> {
+        let mut guards: Vec<_> = self
+            .guard
+            .iter()
+            .map(|(name, _)| Some(name.as_str()))
+            .collect();
+        guards.extend(self.custom.iter().flat_map(|c| c.guards()));
+        guards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CompletionEvent;
+    use std::any::Any;
+
+    #[test]
+    fn test_scxml_import() {
+        use crate::scxml::{self, Registry, ScxmlError};
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Working;
+        #[derive(Debug, PartialEq)]
+        struct Step1;
+        #[derive(Debug, PartialEq)]
+        struct Step2;
+        #[derive(Debug, PartialEq)]
+        struct Done;
+
+        #[derive(Debug, PartialEq)]
+        struct Start(u32);
+        #[derive(Debug, PartialEq)]
+        struct Next;
+        #[derive(Debug, PartialEq)]
+        struct Pause;
+
+        let registry = || {
+            Registry::<dyn Any>::new()
+                .state("idle", || Idle)
+                .state("working", || Working)
+                .state("step1", || Step1)
+                .state("step2", || Step2)
+                .state("done", || Done)
+                .event::<Start>("start")
+                .event::<Next>("next")
+                .event::<Pause>("pause")
+                .event::<CompletionEvent>("done.state.working")
+                .guard("positive", |e: &Start| e.0 > 0)
+        };
+        let document = r#"
+            <scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0" initial="idle">
+                <state id="idle">
+                    <transition event="start" cond="positive" target="working"/>
+                </state>
+                <state id="working">
+                    <initial><transition target="step1"/></initial>
+                    <history id="h" type="shallow"/>
+                    <state id="step1"><transition event="next" target="step2"/></state>
+                    <state id="step2"><transition event="next" target="end"/></state>
+                    <final id="end"/>
+                    <transition event="pause" target="idle"/>
+                    <transition event="done.state.working" target="done"/>
+                </state>
+                <final id="done"/>
+            </scxml>
+        "#;
+
+        let mut sm = scxml::load(document, registry().state("end", || Done))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+        assert!(sm.process(Start(0)).is_err());
+        assert_eq!(sm.process(Start(1)), Ok(()));
+        assert_eq!(sm.process(Next), Ok(()));
+        assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Step2));
+        assert_eq!(sm.process(Pause), Ok(()));
+        assert_eq!(sm.process(Start(1)), Ok(()));
+        assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Step2));
+        assert_eq!(sm.process(Next), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Done));
+
+        assert_eq!(
+            scxml::load(document, registry()).unwrap_err(),
+            ScxmlError::UnknownState("end".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scxml_context() {
+        use crate::guard;
+        use crate::scxml::{self, Registry};
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Done;
+        #[derive(Debug, PartialEq)]
+        struct Start(u32);
+
+        let registry = Registry::<dyn Any, u32>::new()
+            .state("idle", || Idle)
+            .state("done", || Done)
+            .event::<Start>("start")
+            .guard(
+                "allowed",
+                guard::with_ctx(|e: &Start, limit: &mut u32| e.0 < *limit),
+            );
+        let document = r#"
+            <scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0" initial="idle">
+                <state id="idle">
+                    <transition event="start" cond="allowed" target="done"/>
+                </state>
+                <final id="done"/>
+            </scxml>
+        "#;
+
+        let mut sm = scxml::load(document, registry)
+            .unwrap()
+            .build_with_ctx(2)
+            .unwrap();
+        assert!(sm.process(Start(2)).is_err());
+        assert_eq!(sm.process(Start(1)), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Done));
+    }
+}
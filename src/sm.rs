@@ -1,5 +1,10 @@
+mod analysis;
+#[allow(clippy::module_inception)]
 mod sm;
 mod sm_builder;
+mod validation;
 
+pub use analysis::Analysis;
 pub use sm::{Sm, SmError};
-pub use sm_builder::SmBuilder;
+pub use sm_builder::{BuildError, SmBuilder};
+pub(crate) use validation::{TransitionInfo, Validate, VertexInfo};
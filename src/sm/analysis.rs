@@ -0,0 +1,246 @@
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Report of the static analysis of the state machine graph, see `SmBuilder::analyze`.
+///
+/// Vertexes are reported by names of their data types in the order of registration, the
+/// initial pseudo-state is the first one. Nested state machines are not analyzed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Analysis {
+    /// Vertexes that can be reached from the initial and history pseudo-states.
+    pub reachable: Vec<&'static str>,
+    /// Vertexes that have no outgoing transitions, except the ones the state machine may stay
+    /// in: final states, composite states, terminate and history pseudo-states. These are the
+    /// vertexes `SmBuilder::build` reports with `BuildError::NoOutgoingTransitions`. A transition
+    /// to an unregistered vertex still counts as an outgoing one.
+    pub sinks: Vec<&'static str>,
+    /// Strongly connected components of the transition graph. Every vertex belongs to exactly
+    /// one component.
+    pub components: Vec<Vec<&'static str>>,
+    /// Vertexes from which no final state or terminate pseudo-state can be reached.
+    pub dead_ends: Vec<&'static str>,
+}
+
+/// Transition graph of the state machine.
+pub(crate) struct Graph {
+    pub(crate) names: Vec<&'static str>,
+    /// Outgoing edges of every vertex.
+    pub(crate) edges: Vec<Vec<usize>>,
+    /// Vertexes the analysis starts from.
+    pub(crate) entries: Vec<usize>,
+    /// Final states and terminate pseudo-states.
+    pub(crate) exits: Vec<usize>,
+    /// Vertexes that have no outgoing transitions, unless they may stay without them, see
+    /// `VertexInfo::may_stay`.
+    pub(crate) sinks: Vec<usize>,
+}
+
+impl Analysis {
+    pub(crate) fn new(graph: &Graph) -> Self {
+        let names = |set: &BTreeSet<usize>| -> Vec<&'static str> {
+            (0..graph.names.len())
+                .filter(|i| set.contains(i))
+                .map(|i| graph.names[i])
+                .collect()
+        };
+
+        let reachable = graph.reachable();
+        let sinks: BTreeSet<_> = graph.sinks.iter().copied().collect();
+
+        let mut reversed = vec![vec![]; graph.names.len()];
+        for (from, tos) in graph.edges.iter().enumerate() {
+            for &to in tos {
+                reversed[to].push(from);
+            }
+        }
+        let live = graph.reach(&graph.exits, &reversed);
+        let dead_ends: BTreeSet<_> = (0..graph.names.len())
+            .filter(|i| !live.contains(i))
+            .collect();
+
+        Analysis {
+            reachable: names(&reachable),
+            sinks: names(&sinks),
+            components: graph
+                .components()
+                .into_iter()
+                .map(|c| c.into_iter().map(|i| graph.names[i]).collect())
+                .collect(),
+            dead_ends: names(&dead_ends),
+        }
+    }
+}
+
+impl Graph {
+    /// Returns vertexes reachable from the entries including themselves.
+    pub(crate) fn reachable(&self) -> BTreeSet<usize> {
+        self.reach(&self.entries, &self.edges)
+    }
+
+    /// Returns vertexes reachable from `from` including themselves.
+    fn reach(&self, from: &[usize], edges: &[Vec<usize>]) -> BTreeSet<usize> {
+        let mut reached: BTreeSet<usize> = from.iter().copied().collect();
+        let mut stack = from.to_vec();
+        while let Some(i) = stack.pop() {
+            for &j in &edges[i] {
+                if reached.insert(j) {
+                    stack.push(j);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Returns strongly connected components using the Tarjan's algorithm. Components are
+    /// ordered by their first vertex, vertexes inside components are ordered too.
+    fn components(&self) -> Vec<Vec<usize>> {
+        struct Tarjan<'a> {
+            edges: &'a [Vec<usize>],
+            index: Vec<Option<usize>>,
+            lowlink: Vec<usize>,
+            on_stack: Vec<bool>,
+            stack: Vec<usize>,
+            next: usize,
+            components: Vec<Vec<usize>>,
+        }
+
+        impl Tarjan<'_> {
+            fn visit(&mut self, v: usize) {
+                self.index[v] = Some(self.next);
+                self.lowlink[v] = self.next;
+                self.next += 1;
+                self.stack.push(v);
+                self.on_stack[v] = true;
+
+                for &w in &self.edges[v] {
+                    match self.index[w] {
+                        None => {
+                            self.visit(w);
+                            self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
+                        }
+                        Some(index) if self.on_stack[w] => {
+                            self.lowlink[v] = self.lowlink[v].min(index);
+                        }
+                        Some(_) => {}
+                    }
+                }
+
+                if Some(self.lowlink[v]) == self.index[v] {
+                    let mut component = vec![];
+                    loop {
+                        let w = self.stack.pop().unwrap();
+                        self.on_stack[w] = false;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    component.sort_unstable();
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let n = self.names.len();
+        let mut tarjan = Tarjan {
+            edges: &self.edges,
+            index: vec![None; n],
+            lowlink: vec![0; n],
+            on_stack: vec![false; n],
+            stack: vec![],
+            next: 0,
+            components: vec![],
+        };
+        for v in 0..n {
+            if tarjan.index[v].is_none() {
+                tarjan.visit(v);
+            }
+        }
+        let mut components = tarjan.components;
+        components.sort_unstable_by_key(|c| c[0]);
+        components
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{EnterSmEvent, SmBuilder};
+    use std::any::Any;
+
+    #[test]
+    fn test_analysis() {
+        use std::any::type_name;
+
+        struct A;
+        struct B;
+        struct Done;
+        struct Stuck;
+        struct Loop;
+        struct Dead;
+        struct Nested;
+
+        let analysis = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<A>::new().to_vertex())
+            .register_vertex(SimpleVertex::<B>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+            .register_vertex(SimpleVertex::<Stuck>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Loop>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Dead>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Nested>::new().to_submachine_vertex(|| {
+                SmBuilder::new()
+                    .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+                    .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Done))
+            }))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| A))
+            .transition(ftrans(|_: A, _: i32| B))
+            .transition(ftrans(|_: B, _: i32| A))
+            .transition(ftrans(|_: B, _: u32| Done))
+            .transition(ftrans(|_: A, _: u32| Stuck))
+            .transition(ftrans(|_: A, _: u8| Loop))
+            .transition(ftrans(|_: Loop, _: u8| Loop))
+            .transition(ftrans(|_: A, _: u16| Nested))
+            .analyze();
+
+        assert_eq!(
+            analysis.reachable,
+            vec![
+                type_name::<InitialPseudoState>(),
+                type_name::<A>(),
+                type_name::<B>(),
+                type_name::<Done>(),
+                type_name::<Stuck>(),
+                type_name::<Loop>(),
+                type_name::<Nested>(),
+            ]
+        );
+        // Composite states may stay without outgoing transitions, as in `SmBuilder::build`.
+        assert_eq!(
+            analysis.sinks,
+            vec![type_name::<Stuck>(), type_name::<Dead>()]
+        );
+        assert_eq!(
+            analysis.components,
+            vec![
+                vec![type_name::<InitialPseudoState>()],
+                vec![type_name::<A>(), type_name::<B>()],
+                vec![type_name::<Done>()],
+                vec![type_name::<Stuck>()],
+                vec![type_name::<Loop>()],
+                vec![type_name::<Dead>()],
+                vec![type_name::<Nested>()],
+            ]
+        );
+        assert_eq!(
+            analysis.dead_ends,
+            vec![
+                type_name::<Stuck>(),
+                type_name::<Loop>(),
+                type_name::<Dead>(),
+                type_name::<Nested>(),
+            ]
+        );
+    }
+}
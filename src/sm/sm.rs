@@ -1,68 +1,392 @@
-use crate::event::{EnterSmEvent, Event};
+use crate::event::{CompletionEvent, EnterSmEvent, Event, EventQueue, SmEvent};
+use crate::export::Diagram;
 use crate::state::Cast;
-use crate::transition::{Transition, TransitionError, TransitionErrorKind, TransitionOut};
-use crate::vertex::{PseudoStateKind, StateTrait, Vertex};
-use std::any::{Any, TypeId};
-use std::collections::HashMap;
-use std::fmt::{Debug, Formatter};
+use crate::transition::{
+    Transition, TransitionError, TransitionErrorKind, TransitionKind, TransitionOut,
+};
+use crate::vertex::{PseudoState, PseudoStateKind, StateTrait, Vertex};
+use crate::BuildError;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem;
 
-pub struct Sm<DynData: ?Sized = dyn Any> {
+/// State machine.
+///
+/// `E` is the type of events accepted by `process`. By default any type is accepted, use
+/// `SmBuilder::with_events` to restrict events to the enum deriving `SmEvent`.
+///
+/// `Ctx` is the context of the state machine. It is passed by `&mut` to transitions, guards,
+/// entry and exit actions, and it can be accessed with `ctx` and `ctx_mut` between events.
+pub struct Sm<DynData: ?Sized = dyn Any, E = Event, Ctx = ()> {
     state: usize,
-    vertexes: Vec<Vertex<DynData>>,
-    transitions: HashMap<TypeId, Vec<Box<dyn Transition<DynData>>>>,
+    vertexes: Vec<Vertex<DynData, Ctx>>,
+    transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+    terminated: bool,
+    /// Events deferred by the current state.
+    deferred: VecDeque<Event>,
+    /// Set when a state is entered by an external transition, deferred events are replayed
+    /// then.
+    entered: bool,
+    /// Errors of events that were not passed to `process` directly, see `take_errors`.
+    errors: Vec<SmError<Event>>,
+    /// Events posted by actions. Only the outermost state machine processes them.
+    queue: EventQueue,
+    /// Context of the state machine. It is taken while the event is processed, and nested state
+    /// machines have none, they receive the context of the outer one.
+    ctx: Option<Ctx>,
+    _events: PhantomData<fn(E)>,
 }
 
-impl<DynData: ?Sized> Debug for Sm<DynData> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl<DynData: ?Sized, E, Ctx> Debug for Sm<DynData, E, Ctx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Sm").finish()
     }
 }
 
-impl<DynData> Sm<DynData>
+impl<DynData, Ctx> Sm<DynData, Event, Ctx>
 where
-    DynData: Cast<Sm<DynData>> + ?Sized + 'static,
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
 {
     /// Creates a new state machine.
     ///
     /// Note that first vertex in the list must be default, otherwise behaviour is unspecified.
     /// Probably sm will fail on first `sm.process()` call.
     pub fn new(
-        vertexes: Vec<Vertex<DynData>>,
-        transitions: HashMap<TypeId, Vec<Box<dyn Transition<DynData>>>>,
+        vertexes: Vec<Vertex<DynData, Ctx>>,
+        transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+    ) -> Result<Self, SmError<EnterSmEvent>>
+    where
+        Ctx: Default,
+    {
+        Self::with_ctx(vertexes, transitions, Ctx::default())
+    }
+
+    /// Creates a new state machine with the context, see `new`.
+    pub fn with_ctx(
+        vertexes: Vec<Vertex<DynData, Ctx>>,
+        transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+        ctx: Ctx,
+    ) -> Result<Self, SmError<EnterSmEvent>> {
+        Self::with_queue(vertexes, transitions, ctx, EventQueue::new())
+    }
+
+    /// Creates a new state machine with the context, that processes events posted to `queue`.
+    pub(crate) fn with_queue(
+        vertexes: Vec<Vertex<DynData, Ctx>>,
+        transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+        ctx: Ctx,
+        queue: EventQueue,
+    ) -> Result<Self, SmError<EnterSmEvent>> {
+        let mut this = Self::uninit(vertexes, transitions, Some(ctx));
+        this.queue = queue;
+        this.init()?;
+        Ok(this)
+    }
+
+    /// Creates the nested state machine, that uses the context of the outer one.
+    pub(crate) fn nested(
+        vertexes: Vec<Vertex<DynData, Ctx>>,
+        transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+        ctx: &mut Ctx,
     ) -> Result<Self, SmError<EnterSmEvent>> {
-        let mut this = Sm {
+        let mut this = Self::uninit(vertexes, transitions, None);
+        this.init_in(ctx)?;
+        Ok(this)
+    }
+
+    fn uninit(
+        vertexes: Vec<Vertex<DynData, Ctx>>,
+        transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+        ctx: Option<Ctx>,
+    ) -> Self {
+        Sm {
             state: 0,
             vertexes,
             transitions,
-        };
-        this.init()?;
-        Ok(this)
+            terminated: false,
+            deferred: VecDeque::new(),
+            entered: false,
+            errors: Vec::new(),
+            queue: EventQueue::new(),
+            ctx,
+            _events: PhantomData,
+        }
+    }
+
+    pub fn process<E: Any + 'static>(&mut self, event: E) -> Result<(), SmError<E>> {
+        self.process_as(Box::new(event))
+    }
+
+    /// Restricts events of the state machine to the event enum `E`, whose transitions are
+    /// checked by `SmBuilder` with the same enum.
+    pub(crate) fn with_events<E: SmEvent>(self) -> Sm<DynData, E, Ctx> {
+        Sm {
+            state: self.state,
+            vertexes: self.vertexes,
+            transitions: self.transitions,
+            terminated: self.terminated,
+            deferred: self.deferred,
+            entered: self.entered,
+            errors: self.errors,
+            queue: self.queue,
+            ctx: self.ctx,
+            _events: PhantomData,
+        }
+    }
+}
+
+impl<DynData, E, Ctx> Sm<DynData, E, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+    E: SmEvent,
+{
+    /// Processes the event of the event enum, see `SmEvent`.
+    pub fn process(&mut self, event: E) -> Result<(), SmError<E>> {
+        let variant = event.variant();
+        self.process_boxed(event.into_event())
+            .map_err(|e| e.map(|event| E::from_event(variant, event)))
+    }
+}
+
+impl<DynData, E, Ctx> Sm<DynData, E, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    /// Returns the context of the state machine. Nested state machines use the context of the
+    /// outer one and return `None`.
+    pub fn ctx(&self) -> Option<&Ctx> {
+        self.ctx.as_ref()
+    }
+
+    /// Returns the context of the state machine for modification, see `ctx`.
+    pub fn ctx_mut(&mut self) -> Option<&mut Ctx> {
+        self.ctx.as_mut()
+    }
+
+    /// Calls `f` with the context taken from the state machine.
+    fn in_ctx<R>(&mut self, f: impl FnOnce(&mut Self, &mut Ctx) -> R) -> R {
+        let mut ctx = self
+            .ctx
+            .take()
+            .expect("Only the outermost state machine has the context.");
+        let result = f(self, &mut ctx);
+        self.ctx = Some(ctx);
+        result
     }
 
     /// Init state machine state.
+    ///
+    /// If the state machine has a history pseudo-state with the default transition, it is used
+    /// instead of the initial one.
     pub fn init(&mut self) -> Result<(), SmError<EnterSmEvent>> {
+        self.in_ctx(|sm, ctx| {
+            sm.init_in(ctx)?;
+            sm.run_queue(ctx);
+            Ok(())
+        })
+    }
+
+    fn init_in(&mut self, ctx: &mut Ctx) -> Result<(), SmError<EnterSmEvent>> {
         assert_eq!(self.state, 0);
-        self.process(EnterSmEvent)
+        if let Some(history) = self.find_history() {
+            if self
+                .transitions
+                .contains_key(&self.vertexes[history].data_tid())
+            {
+                self.state = history;
+            }
+        }
+        self.process_in(Box::new(EnterSmEvent), ctx)
+            .map_err(|e| e.map(|e| *e.downcast().unwrap()))
     }
 
     /// Drop current state and revert StateMachine to the pre-initial uninitialized state.
     pub fn drop_state(&mut self) -> Result<(), SmError<EnterSmEvent>> {
         assert_eq!(self.state, 0);
-        self.process(EnterSmEvent)
+        self.process_as(Box::new(EnterSmEvent))
     }
 
-    pub fn process<E: Any + 'static>(&mut self, event: E) -> Result<(), SmError<E>> {
-        self.process_boxed(Box::new(event)).map_err(|e| match e {
-            SmError::NoTransitionSatisfyingEvent(e) => {
-                SmError::NoTransitionSatisfyingEvent(*e.downcast().unwrap())
+    /// Processes the boxed event of type `T` and returns it back on error.
+    fn process_as<T: Any>(&mut self, event: Event) -> Result<(), SmError<T>> {
+        self.process_boxed(event)
+            .map_err(|e| e.map(|e| *e.downcast().unwrap()))
+    }
+
+    /// Processes the event.
+    ///
+    /// Transitions from the current state are tried first. If none of them handles the event
+    /// and the current state is a composite state, the event is passed to the nested state
+    /// machine.
+    ///
+    /// If the event is not handled, but the current state defers it, the event is kept and
+    /// processed again after the next external transition, including a self-transition. Errors
+    /// of replayed events are kept, see `take_errors`.
+    ///
+    /// Terminated state machine rejects all events with `SmError::Terminated`.
+    ///
+    /// When the step is completed, events posted to the queue are processed, see
+    /// `process_queue`. The result is only about the event itself, errors of posted events are
+    /// kept, see `take_errors`.
+    pub fn process_boxed(&mut self, event: Event) -> Result<(), SmError<Event>> {
+        self.in_ctx(|sm, ctx| {
+            let result = sm.process_in(event, ctx);
+            sm.run_queue(ctx);
+            result
+        })
+    }
+
+    /// Takes errors of events that were not passed to `process` directly: replayed deferred
+    /// events that are neither handled nor deferred again, including the ones of nested state
+    /// machines, and events posted to the queue.
+    pub fn take_errors(&mut self) -> Vec<SmError<Event>> {
+        mem::take(&mut self.errors)
+    }
+
+    /// Returns the queue of events posted by actions, see `SmBuilder::queue`.
+    pub fn queue(&self) -> &EventQueue {
+        &self.queue
+    }
+
+    /// Processes events posted to the queue until it is empty. Every event is processed only
+    /// after the step of the previous one is completed, internal events before external ones.
+    ///
+    /// Returns errors of events that are neither handled nor deferred, see `take_errors`.
+    pub fn process_queue(&mut self) -> Vec<SmError<Event>> {
+        self.in_ctx(Self::run_queue);
+        self.take_errors()
+    }
+
+    fn run_queue(&mut self, ctx: &mut Ctx) {
+        while let Some(event) = self.queue.pop() {
+            if let Err(err) = self.process_in(event, ctx) {
+                self.errors.push(err);
+            }
+        }
+    }
+
+    /// Processes the event with the context, see `process_boxed`.
+    pub(crate) fn process_in(&mut self, event: Event, ctx: &mut Ctx) -> Result<(), SmError<Event>> {
+        self.entered = false;
+        self.dispatch(event, ctx).or_else(|err| self.defer(err))?;
+        if self.entered {
+            self.replay(ctx);
+        }
+        Ok(())
+    }
+
+    /// Keeps the event that is not handled, if the current state defers it.
+    fn defer(&mut self, err: SmError<Event>) -> Result<(), SmError<Event>> {
+        match err {
+            SmError::NoTransitionsFromThisVertex(event)
+            | SmError::NoTransitionSatisfyingEvent(event)
+            | SmError::GuardRejected { event, .. }
+                if self.vertexes[self.state].defers((*event).type_id()) =>
+            {
+                self.deferred.push_back(event);
+                Ok(())
+            }
+            err => Err(err),
+        }
+    }
+
+    /// Processes deferred events again in the order of arrival. Errors of events that are
+    /// neither handled nor deferred anymore are kept in `errors`.
+    fn replay(&mut self, ctx: &mut Ctx) {
+        let mut pending = mem::take(&mut self.deferred);
+        while let Some(event) = pending.pop_front() {
+            self.entered = false;
+            if let Err(err) = self.dispatch(event, ctx).or_else(|err| self.defer(err)) {
+                self.errors.push(err);
             }
-            SmError::NoTransitionsFromThisVertex(e) => {
-                SmError::NoTransitionsFromThisVertex(*e.downcast().unwrap())
+            if self.entered {
+                // Events deferred again are tried first in the new state.
+                self.deferred.append(&mut pending);
+                pending = mem::take(&mut self.deferred);
             }
-        })
+        }
     }
 
-    pub fn process_boxed(&mut self, event: Event) -> Result<(), SmError<Event>> {
+    /// Processes the event by the current state or by the nested state machine.
+    fn dispatch(&mut self, event: Event, ctx: &mut Ctx) -> Result<(), SmError<Event>> {
+        if self.terminated {
+            return Err(SmError::Terminated(event));
+        }
+        let err = match self.process_current(event, ctx) {
+            Ok(()) => return self.complete_step(ctx),
+            Err(e) => e,
+        };
+        let result = match &mut self.vertexes[self.state] {
+            Vertex::SubMachineState(sub) => match sub.region.sm.as_mut() {
+                Some(sm) => sm.process_in(err.unhandled()?, ctx),
+                None => return Err(err),
+            },
+            Vertex::OrthogonalState(o) => o.process_in(err.unhandled()?, ctx),
+            _ => return Err(err),
+        };
+        for region in self.vertexes[self.state].regions_mut() {
+            if let Some(sm) = region.sm.as_mut() {
+                self.errors.append(&mut sm.errors);
+            }
+        }
+        result?;
+        self.complete_step(ctx)
+    }
+
+    /// Returns `true` if the state machine entered the terminate pseudo-state.
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// Returns `true` if the state machine is in the final state.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.vertexes[self.state], Vertex::FinalState(_))
+    }
+
+    /// Finishes the step of the state machine.
+    ///
+    /// The state machine is terminated if the current state is the terminate pseudo-state, or if
+    /// any nested state machine of the current state is terminated. If all nested state machines
+    /// are finished, `CompletionEvent` is processed.
+    fn complete_step(&mut self, ctx: &mut Ctx) -> Result<(), SmError<Event>> {
+        let (terminated, finished) = match &self.vertexes[self.state] {
+            Vertex::PseudoState(PseudoState {
+                kind: PseudoStateKind::Terminate,
+                ..
+            }) => (true, false),
+            Vertex::SubMachineState(sub) => match &sub.region.sm {
+                Some(sm) => (sm.is_terminated(), sm.is_finished()),
+                None => (false, false),
+            },
+            Vertex::OrthogonalState(o) => (
+                o.machines().any(Sm::is_terminated),
+                o.machines().all(Sm::is_finished),
+            ),
+            _ => (false, false),
+        };
+        if terminated {
+            self.exit(ctx);
+            self.terminated = true;
+        } else if finished {
+            match self.process_current(Box::new(CompletionEvent), ctx) {
+                Ok(()) => return self.complete_step(ctx),
+                Err(err) => {
+                    err.unhandled()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn process_current(&mut self, event: Event, ctx: &mut Ctx) -> Result<(), SmError<Event>> {
         let state = &mut self.vertexes[self.state];
         let state_tid = state.data_tid();
 
@@ -71,16 +395,43 @@ where
             None => return Err(SmError::NoTransitionsFromThisVertex(event)),
         };
         let mut event = event;
-        for transition in transitions {
-            match transition.transition(state, event) {
-                Ok(TransitionOut { state: new_state }) => {
+        let mut rejected = None;
+        let event_tid = event.as_ref().type_id();
+        for transition in transitions
+            .iter()
+            .filter(|t| t.event_type().tid == event_tid)
+        {
+            if transition.kind() == TransitionKind::Local
+                && !has_nested_target(state, transition.as_ref())
+            {
+                // The nested state machine failed to start, so the local transition is not
+                // performed and the composite state stays as it is.
+                continue;
+            }
+            match transition.transition(state, event, ctx) {
+                Ok(TransitionOut::Stay) => return Ok(()),
+                Ok(TransitionOut::Target { state: new_state })
+                    if transition.kind() == TransitionKind::Local =>
+                {
+                    let tid = new_state.as_ref().type_id();
+                    return state
+                        .regions_mut()
+                        .iter_mut()
+                        .filter_map(|region| region.sm.as_mut())
+                        .find(|sm| sm.find_vertex_by_data_tid(tid).is_some())
+                        .expect("It should be checked by `has_nested_target`")
+                        .enter(new_state, ctx);
+                }
+                Ok(TransitionOut::Target { state: new_state }) => {
                     let new_vertex = self
                         .find_vertex_by_data_tid(new_state.as_ref().type_id())
                         .expect("It should be checked in the `transition` function");
                     self.vertexes[new_vertex].set_data(new_state);
-                    self.vertexes[new_vertex].entry();
                     self.state = new_vertex;
-                    return Ok(());
+                    self.entered = true;
+                    return self.vertexes[new_vertex]
+                        .entry(ctx)
+                        .map_err(|e| SmError::SubmachineFailed(Box::new(e)));
                 }
                 Err(e) => {
                     let TransitionError {
@@ -88,6 +439,11 @@ where
                         kind,
                     } = e;
                     match kind {
+                        TransitionErrorKind::NamedGuardErr(guard) => {
+                            rejected.get_or_insert(guard);
+                            event = event1;
+                            continue;
+                        }
                         TransitionErrorKind::GuardErr | TransitionErrorKind::WrongEvent => {
                             event = event1;
                             continue;
@@ -97,7 +453,70 @@ where
             }
         }
 
-        Err(SmError::NoTransitionSatisfyingEvent(event))
+        match rejected {
+            Some(guard) => Err(SmError::GuardRejected { event, guard }),
+            None => Err(SmError::NoTransitionSatisfyingEvent(event)),
+        }
+    }
+
+    /// Moves the state machine to the state with `data` as the target of the local transition of
+    /// the enclosing composite state. The current state is exited first.
+    pub(crate) fn enter(
+        &mut self,
+        data: Box<DynData>,
+        ctx: &mut Ctx,
+    ) -> Result<(), SmError<Event>> {
+        let target = self
+            .find_vertex_by_data_tid(data.as_ref().type_id())
+            .expect("It should be checked by `SmBuilder::build`");
+        self.exit(ctx);
+        self.vertexes[self.state].get_data();
+        self.vertexes[target].set_data(data);
+        self.state = target;
+        self.vertexes[target]
+            .entry(ctx)
+            .map_err(|e| SmError::SubmachineFailed(Box::new(e)))?;
+        self.complete_step(ctx)?;
+        self.replay(ctx);
+        Ok(())
+    }
+
+    /// Enters the current state again after `exit`. If `deep` is set, the whole configuration of
+    /// the nested composite states is restored, otherwise they are entered as usual.
+    pub(crate) fn resume(&mut self, deep: bool, ctx: &mut Ctx) -> Result<(), BuildError> {
+        match &mut self.vertexes[self.state] {
+            Vertex::SubMachineState(sub) if deep => sub.entry_deep(ctx),
+            Vertex::OrthogonalState(o) if deep => o.entry_deep(ctx),
+            vertex => vertex.entry(ctx),
+        }
+    }
+
+    /// Returns kind of the history pseudo-state, if the state machine has one.
+    pub(crate) fn history_kind(&self) -> Option<&PseudoStateKind<Ctx>> {
+        self.find_history().map(|i| match &self.vertexes[i] {
+            Vertex::PseudoState(ps) => &ps.kind,
+            _ => unreachable!(),
+        })
+    }
+
+    fn find_history(&self) -> Option<usize> {
+        self.vertexes.iter().position(|v| {
+            matches!(
+                v,
+                Vertex::PseudoState(PseudoState {
+                    kind: PseudoStateKind::ShallowHistory | PseudoStateKind::DeepHistory,
+                    ..
+                })
+            )
+        })
+    }
+
+    /// Calls exit points of the current state and of all active nested states, from the
+    /// innermost to the outermost one.
+    pub(crate) fn exit(&mut self, ctx: &mut Ctx) {
+        if !self.terminated {
+            self.vertexes[self.state].exit(ctx);
+        }
     }
 
     pub fn current_state(&self) -> &DynData {
@@ -112,6 +531,68 @@ where
         DynData::downcast_ref(self.current_state())
     }
 
+    /// Returns data of the current state and of all active nested states, from the outermost
+    /// to the innermost one.
+    ///
+    /// The path ends at the state with orthogonal regions, use `active_states` to get states
+    /// inside the regions.
+    pub fn current_state_path(&self) -> Vec<&DynData> {
+        let mut path = vec![self.current_state()];
+        if let Vertex::SubMachineState(sub) = &self.vertexes[self.state] {
+            if let Some(sm) = &sub.region.sm {
+                path.extend(sm.current_state_path());
+            }
+        }
+        path
+    }
+
+    /// Returns data of all active leaf states, i.e. active states that are not composite.
+    pub fn active_states(&self) -> Vec<&DynData> {
+        match &self.vertexes[self.state] {
+            Vertex::SubMachineState(sub) => match &sub.region.sm {
+                Some(sm) => sm.active_states(),
+                None => vec![self.current_state()],
+            },
+            Vertex::OrthogonalState(o) => o.machines().flat_map(Sm::active_states).collect(),
+            _ => vec![self.current_state()],
+        }
+    }
+
+    /// Renders the state machine in the Graphviz DOT language. Vertexes are labelled with names
+    /// of their data types, transitions with names of their event types and guards. Guarded
+    /// transitions are dashed, the current state is filled.
+    pub fn to_dot(&self) -> String {
+        self.diagram().to_dot()
+    }
+
+    /// Renders the state machine as a PlantUML state diagram. Composite states contain nested
+    /// state machines as they are described, states with entry or exit actions are annotated,
+    /// and guarded transitions are labelled with names of their guards, like `[even && guarded]`,
+    /// see `guard::named`.
+    pub fn to_plantuml(&self) -> String {
+        self.diagram().to_plantuml()
+    }
+
+    /// Renders the state machine as a Mermaid `stateDiagram-v2`. Transitions are labelled with
+    /// names of their event types and guards, composite states contain nested state machines as they are
+    /// described, and the current state has the `current` class.
+    pub fn to_mermaid(&self) -> String {
+        self.diagram().to_mermaid()
+    }
+
+    /// Renders the topology of the state machine as a SCXML document, so that it can be opened
+    /// in external SCXML tools. States and events are named after their types, guards are
+    /// named by `Guard::name`, see `guard::named`. Guards without names get identifiers made from
+    /// the source state, the event and the number of the guard among unnamed guards of
+    /// transitions from the state on the event. The current state is not exported.
+    pub fn to_scxml(&self) -> String {
+        self.diagram().to_scxml()
+    }
+
+    fn diagram(&self) -> Diagram {
+        Diagram::new(&self.vertexes, &self.transitions, Some(self.state))
+    }
+
     fn find_vertex_by_data_tid(&self, tid: TypeId) -> Option<usize> {
         self.vertexes
             .iter()
@@ -121,8 +602,385 @@ where
     }
 }
 
+/// Returns `true` if the running nested state machine of the composite `state` has the target
+/// of the local `transition`.
+fn has_nested_target<DynData: ?Sized + 'static, Ctx: 'static>(
+    state: &Vertex<DynData, Ctx>,
+    transition: &dyn Transition<DynData, Ctx>,
+) -> bool {
+    let outputs = transition.output_types();
+    state
+        .regions()
+        .iter()
+        .filter_map(|region| region.sm.as_ref())
+        .any(|sm| {
+            outputs
+                .iter()
+                .all(|output| sm.find_vertex_by_data_tid(output.tid).is_some())
+        })
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum SmError<Event> {
     NoTransitionsFromThisVertex(Event),
     NoTransitionSatisfyingEvent(Event),
+    /// The same as `NoTransitionSatisfyingEvent`, but the event is rejected by the guard with
+    /// the name, see `guard::named`. If several named guards reject the event, it is the first
+    /// of them.
+    GuardRejected {
+        event: Event,
+        guard: String,
+    },
+    /// State machine is terminated and cannot be used anymore.
+    Terminated(Event),
+    /// The event is handled, but the nested state machine of the entered composite state
+    /// failed to start, e.g. no guard of its initial transitions is satisfied. The composite
+    /// state stays active without it, and its local transitions are not performed.
+    SubmachineFailed(Box<BuildError>),
+}
+
+impl<Event> SmError<Event> {
+    /// Maps the event that was not processed.
+    pub fn map<T>(self, f: impl FnOnce(Event) -> T) -> SmError<T> {
+        match self {
+            SmError::NoTransitionsFromThisVertex(e) => SmError::NoTransitionsFromThisVertex(f(e)),
+            SmError::NoTransitionSatisfyingEvent(e) => SmError::NoTransitionSatisfyingEvent(f(e)),
+            SmError::GuardRejected { event, guard } => SmError::GuardRejected {
+                event: f(event),
+                guard,
+            },
+            SmError::Terminated(e) => SmError::Terminated(f(e)),
+            SmError::SubmachineFailed(e) => SmError::SubmachineFailed(e),
+        }
+    }
+
+    /// Returns the event that was not processed, if any.
+    pub fn into_event(self) -> Option<Event> {
+        self.unhandled().ok()
+    }
+
+    /// Returns the event that was not processed, or the error itself if the event is handled.
+    pub(crate) fn unhandled(self) -> Result<Event, Self> {
+        match self {
+            SmError::NoTransitionsFromThisVertex(e) => Ok(e),
+            SmError::NoTransitionSatisfyingEvent(e) => Ok(e),
+            SmError::GuardRejected { event, .. } => Ok(event),
+            SmError::Terminated(e) => Ok(e),
+            err @ SmError::SubmachineFailed(_) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::GuardedTransition;
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{CompletionEvent, EnterSmEvent, SmBuilder, SmError, Vertex};
+    use std::any::{Any, TypeId};
+
+    #[test]
+    fn test_history() {
+        use crate::state::{DeepHistoryPseudoState, ShallowHistoryPseudoState};
+
+        #[derive(Debug, PartialEq)]
+        struct Active;
+        #[derive(Debug, PartialEq)]
+        struct Paused;
+        #[derive(Debug, PartialEq)]
+        struct Inner;
+        #[derive(Debug, PartialEq)]
+        struct Nested;
+        #[derive(Debug, PartialEq)]
+        struct NestedX;
+        #[derive(Debug, PartialEq)]
+        struct NestedY;
+
+        struct Next;
+        struct Interrupt;
+        struct Resume;
+
+        let make_machine = |deep: bool| {
+            SmBuilder::<dyn Any>::new()
+                .register_vertex(SimpleVertex::<Paused>::new().to_vertex())
+                .register_vertex(SimpleVertex::<Active>::new().to_submachine_vertex(move || {
+                    let builder = SmBuilder::new()
+                        .register_vertex(SimpleVertex::<Inner>::new().to_final_vertex())
+                        .register_vertex(SimpleVertex::<Nested>::new().to_submachine_vertex(|| {
+                            SmBuilder::new()
+                                .register_vertex(SimpleVertex::<NestedX>::new().to_vertex())
+                                .register_vertex(SimpleVertex::<NestedY>::new().to_final_vertex())
+                                .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| {
+                                    NestedX
+                                }))
+                                .transition(ftrans(|_: NestedX, _: Next| NestedY))
+                        }))
+                        .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Inner));
+                    match deep {
+                        true => builder
+                            .register_vertex(Vertex::deep_history())
+                            .transition(ftrans(|_: DeepHistoryPseudoState, _: EnterSmEvent| {
+                                Nested
+                            })),
+                        false => builder
+                            .register_vertex(Vertex::shallow_history())
+                            .transition(ftrans(|_: ShallowHistoryPseudoState, _: EnterSmEvent| {
+                                Nested
+                            })),
+                    }
+                }))
+                .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Active))
+                .transition(ftrans(|_: Active, _: Interrupt| Paused))
+                .transition(ftrans(|_: Paused, _: Resume| Active))
+                .build()
+                .unwrap()
+        };
+
+        for (deep, expected) in [
+            (false, TypeId::of::<NestedX>()),
+            (true, TypeId::of::<NestedY>()),
+        ] {
+            let mut sm = make_machine(deep);
+            let path = sm.current_state_path();
+            assert_eq!(path[1].downcast_ref::<Nested>(), Some(&Nested));
+            assert_eq!(path[2].downcast_ref::<NestedX>(), Some(&NestedX));
+
+            assert!(sm.process(Next).is_ok());
+            assert!(sm.process(Interrupt).is_ok());
+            assert_eq!(sm.current_state_concrete(), Some(&Paused));
+            assert!(sm.process(Resume).is_ok());
+
+            let path = sm.current_state_path();
+            assert_eq!(path.len(), 3);
+            assert_eq!(path[1].downcast_ref::<Nested>(), Some(&Nested));
+            assert_eq!(path[2].type_id(), expected);
+        }
+    }
+
+    #[test]
+    fn test_terminate() {
+        use crate::state::TerminatePseudoState;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq)]
+        struct Active;
+        #[derive(Debug, PartialEq)]
+        struct Inner;
+        #[derive(Debug, PartialEq)]
+        struct Kill;
+
+        let exited = Rc::new(Cell::new(false));
+        let exited1 = exited.clone();
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(
+                SimpleVertex::<Active>::new()
+                    .with_exit(move |_| exited1.set(true))
+                    .to_submachine_vertex(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Inner>::new().to_vertex())
+                            .register_vertex(Vertex::terminate())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Inner))
+                            .transition(ftrans(|_: Inner, _: Kill| TerminatePseudoState))
+                    }),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Active))
+            .build()
+            .unwrap();
+
+        assert!(!sm.is_terminated());
+        assert_eq!(sm.process(Kill), Ok(()));
+        assert!(sm.is_terminated());
+        assert!(exited.get());
+        assert_eq!(sm.process(Kill), Err(SmError::Terminated(Kill)));
+    }
+
+    #[test]
+    fn test_final_state() {
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Active;
+        #[derive(Debug, PartialEq)]
+        struct Inner;
+        #[derive(Debug, PartialEq)]
+        struct InnerDone;
+        #[derive(Debug, PartialEq)]
+        struct Done;
+
+        struct Start;
+        struct Finish;
+        struct Stop;
+
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Active>::new().to_submachine_vertex(|| {
+                SmBuilder::new()
+                    .register_vertex(SimpleVertex::<Inner>::new().to_vertex())
+                    .register_vertex(SimpleVertex::<InnerDone>::new().to_final_vertex())
+                    .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Inner))
+                    .transition(ftrans(|_: Inner, _: Finish| InnerDone))
+            }))
+            .register_vertex(SimpleVertex::<Done>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Start| Active))
+            .transition(ftrans(|_: Active, _: CompletionEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Stop| Done))
+            .build()
+            .unwrap();
+
+        assert!(sm.process(Start).is_ok());
+        assert_eq!(sm.current_state_concrete(), Some(&Active));
+        assert!(sm.process(Finish).is_ok());
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+        assert!(!sm.is_finished());
+        assert!(sm.process(Stop).is_ok());
+        assert!(sm.is_finished());
+    }
+
+    #[test]
+    fn test_deferred_events() {
+        use std::cell::{Cell, RefCell};
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Working;
+        #[derive(Debug, PartialEq)]
+        struct Start;
+        #[derive(Debug, PartialEq)]
+        struct Job(u32);
+        #[derive(Debug, PartialEq)]
+        struct Pause;
+        #[derive(Debug, PartialEq)]
+        struct Stop;
+
+        let done = Rc::new(RefCell::new(vec![]));
+        let done1 = done.clone();
+        let ready = Rc::new(Cell::new(false));
+        let ready1 = ready.clone();
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(
+                SimpleVertex::<Idle>::new()
+                    .defer::<Job>()
+                    .defer::<Pause>()
+                    .to_vertex(),
+            )
+            .register_vertex(SimpleVertex::<Working>::new().defer::<Stop>().to_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Start| Working))
+            .transition(ftrans(move |_: Working, job: Job| {
+                done1.borrow_mut().push(job.0);
+                Working
+            }))
+            .transition(
+                GuardedTransition::new()
+                    .guard(move |_: &Stop| ready1.get())
+                    .transition(ftrans(|_: Working, _: Stop| Idle)),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(sm.process(Job(1)), Ok(()));
+        assert_eq!(sm.process(Pause), Ok(()));
+        assert_eq!(sm.process(Job(2)), Ok(()));
+        assert_eq!(sm.process(3), Err(SmError::NoTransitionSatisfyingEvent(3)));
+        assert!(done.borrow().is_empty());
+
+        // Jobs are replayed in order, `Pause` is not handled in `Working`.
+        assert_eq!(sm.process(Start), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Working));
+        assert_eq!(*done.borrow(), vec![1, 2]);
+        let errors: Vec<_> = sm
+            .take_errors()
+            .into_iter()
+            .map(|err| err.map(|e| *e.downcast::<Pause>().unwrap()))
+            .collect();
+        assert_eq!(errors, vec![SmError::NoTransitionSatisfyingEvent(Pause)]);
+        assert_eq!(
+            sm.process(Pause),
+            Err(SmError::NoTransitionSatisfyingEvent(Pause))
+        );
+        assert!(sm.take_errors().is_empty());
+
+        // The deferred event is replayed after the self-transition.
+        assert_eq!(sm.process(Stop), Ok(()));
+        ready.set(true);
+        assert_eq!(sm.current_state_concrete(), Some(&Working));
+        assert_eq!(sm.process(Job(3)), Ok(()));
+        assert_eq!(*done.borrow(), vec![1, 2, 3]);
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+        assert!(sm.take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_context() {
+        use crate::guard;
+
+        #[derive(Debug, Default)]
+        struct Ctx {
+            coins: u32,
+            limit: u32,
+            log: Vec<String>,
+        }
+        #[derive(Debug, PartialEq)]
+        struct Locked;
+        #[derive(Debug, PartialEq)]
+        struct Unlocked;
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Coin;
+        #[derive(Debug, PartialEq)]
+        struct Push;
+
+        let unlocked = || {
+            SmBuilder::<dyn Any, Ctx>::new()
+                .register_vertex(
+                    SimpleVertex::<Idle, Ctx>::new()
+                        .with_entry_ctx(|_, ctx| ctx.log.push("enter Idle".to_string()))
+                        .to_final_vertex(),
+                )
+                .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+        };
+        let mut sm = SmBuilder::<dyn Any, Ctx>::new()
+            .register_vertex(
+                SimpleVertex::<Locked, Ctx>::new()
+                    .with_exit_ctx(|_, ctx| ctx.log.push("exit Locked".to_string()))
+                    .to_vertex(),
+            )
+            .register_vertex(SimpleVertex::<Unlocked, Ctx>::new().to_submachine_vertex(unlocked))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Locked))
+            .transition(
+                GuardedTransition::new()
+                    .guard(guard::with_ctx(|_: &Coin, ctx: &mut Ctx| {
+                        ctx.coins < ctx.limit
+                    }))
+                    .transition(ftrans(|_: Locked, _: Coin, ctx: &mut Ctx| {
+                        ctx.coins += 1;
+                        Unlocked
+                    })),
+            )
+            .transition(ftrans(|_: Unlocked, _: Push| Locked))
+            .build_with_ctx(Ctx {
+                limit: 1,
+                ..Ctx::default()
+            })
+            .unwrap();
+
+        assert_eq!(sm.process(Coin), Ok(()));
+        assert_eq!(sm.process(Push), Ok(()));
+        assert_eq!(
+            sm.process(Coin),
+            Err(SmError::NoTransitionSatisfyingEvent(Coin))
+        );
+        assert_eq!(sm.ctx().unwrap().coins, 1);
+        assert_eq!(sm.ctx().unwrap().log, vec!["exit Locked", "enter Idle"]);
+
+        sm.ctx_mut().unwrap().limit = 2;
+        assert_eq!(sm.process(Coin), Ok(()));
+        assert_eq!(sm.ctx().unwrap().coins, 2);
+        assert_eq!(sm.current_state_concrete::<Unlocked>(), Some(&Unlocked));
+    }
 }
@@ -1,21 +1,51 @@
-use crate::event::EnterSmEvent;
+use crate::event::{CompletionEvent, EnterSmEvent, Event, EventOf, EventQueue, SmEvent};
+use crate::export::Diagram;
+use crate::sm::analysis::Analysis;
 use crate::sm::sm::Sm;
+use crate::sm::Validate;
 use crate::state::Cast;
-use crate::state::{InitialPseudoState, SimpleVertex};
-use crate::transition::Transition;
+use crate::state::InitialPseudoState;
+use crate::transition::{Transition, TransitionEvent, TransitionKind, TypeInfo};
 use crate::vertex::{PseudoState, PseudoStateKind, StateTrait, Vertex};
 use crate::SmError;
-use std::any::{Any, TypeId};
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
 
-pub struct SmBuilder<DynData: ?Sized = dyn Any> {
-    vertexes: Vec<Vertex<DynData>>,
-    transitions: HashMap<TypeId, Vec<Box<dyn Transition<DynData>>>>,
+/// Builder of the state machine. `Ctx` is the context of the state machine, see `Sm`. `E` is
+/// the type of its events, see `with_events`.
+pub struct SmBuilder<DynData: ?Sized = dyn Any, Ctx = (), E = Event> {
+    vertexes: Vec<Vertex<DynData, Ctx>>,
+    transitions: BTreeMap<TypeId, Vec<Box<dyn Transition<DynData, Ctx>>>>,
+    queue: EventQueue,
+    _events: PhantomData<fn(E)>,
 }
 
-impl<DynData> SmBuilder<DynData>
+impl<DynData: ?Sized, Ctx, E> Debug for SmBuilder<DynData, Ctx, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SmBuilder").finish()
+    }
+}
+
+impl<DynData, Ctx> Default for SmBuilder<DynData, Ctx>
+where
+    DynData: Cast<InitialPseudoState> + ?Sized + 'static,
+    Ctx: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<DynData, Ctx> SmBuilder<DynData, Ctx>
 where
     DynData: ?Sized + 'static,
+    Ctx: 'static,
 {
     pub fn new() -> Self
     where
@@ -25,10 +55,12 @@ where
             Some(Box::new(InitialPseudoState)),
             PseudoStateKind::Initial,
         ))];
-        let transitions = HashMap::new();
+        let transitions = BTreeMap::new();
         SmBuilder {
             vertexes,
             transitions,
+            queue: EventQueue::new(),
+            _events: PhantomData,
         }
     }
     pub fn with_default_state<T>(state: T) -> Self
@@ -40,54 +72,455 @@ where
             Some(Box::new(state)),
             PseudoStateKind::Initial,
         ))];
-        let transitions = HashMap::new();
+        let transitions = BTreeMap::new();
         SmBuilder {
             vertexes,
             transitions,
+            queue: EventQueue::new(),
+            _events: PhantomData,
         }
     }
-    pub fn register_vertex(mut self, vertex: Vertex<DynData>) -> Self {
-        self.vertexes.push(vertex);
-        self
+    pub fn transition<T: Transition<DynData, Ctx> + 'static>(self, transition: T) -> Self {
+        self.push_transition(Box::new(transition))
+    }
+
+    /// Restricts events of the state machine to the event enum `E`, see `SmEvent`.
+    ///
+    /// Transitions registered after that must be triggered by events of the enum or by internal
+    /// events, which is checked by the compiler, see `EventOf`. Transitions registered before
+    /// and transitions of nested state machines are checked by `build`, which reports the ones
+    /// triggered by other events as `BuildError::UnexpectedEvent`.
+    pub fn with_events<E: SmEvent>(self) -> SmBuilder<DynData, Ctx, E> {
+        SmBuilder {
+            vertexes: self.vertexes,
+            transitions: self.transitions,
+            queue: self.queue,
+            _events: PhantomData,
+        }
     }
-    pub fn transition<T: Transition<DynData> + 'static>(mut self, transition: T) -> Self
+
+    /// Builds the state machine. The state machine is validated first, and all found problems
+    /// are reported.
+    pub fn build(self) -> Result<Sm<DynData, Event, Ctx>, Vec<BuildError>>
     where
-        DynData: Cast<Sm<DynData>>,
+        Ctx: Default,
     {
-        assert!(
-            self.find_vertex_by_data_tid(transition.input_tid())
-                .is_some(),
-            "Not found input vertex!"
-        );
-        assert!(
-            self.find_vertex_by_data_tid(transition.output_tid())
-                .is_some(),
-            "Not found output vertex!"
-        );
+        self.build_with_ctx(Ctx::default())
+    }
 
-        let trans = Box::new(transition);
-        self.transitions
-            .entry(trans.input_tid())
-            .or_default()
-            .push(trans);
-        self
+    /// Builds the state machine with the context, see `build`.
+    pub fn build_with_ctx(self, ctx: Ctx) -> Result<Sm<DynData, Event, Ctx>, Vec<BuildError>> {
+        self.validate()?;
+        self.start(ctx)
+    }
+
+    /// Builds the state machine with the context, that is made from the queue of the state
+    /// machine, see `queue`.
+    pub fn build_with_queue<F>(self, ctx: F) -> Result<Sm<DynData, Event, Ctx>, Vec<BuildError>>
+    where
+        F: FnOnce(EventQueue) -> Ctx,
+    {
+        let ctx = ctx(self.queue.clone());
+        self.build_with_ctx(ctx)
+    }
+
+    /// Builds the nested state machine, that uses the context of the outer one.
+    pub(crate) fn build_nested(
+        self,
+        ctx: &mut Ctx,
+    ) -> Result<Sm<DynData, Event, Ctx>, Vec<BuildError>> {
+        self.validate()?;
+        Sm::nested(self.vertexes, self.transitions, ctx).map_err(|e| vec![BuildError::Init(e)])
+    }
+}
+
+impl<DynData, Ctx, E> SmBuilder<DynData, Ctx, E>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+    E: SmEvent,
+{
+    /// Registers the transition triggered by an event of the enum `E` or by an internal event,
+    /// see `with_events`.
+    pub fn transition<T>(self, transition: T) -> Self
+    where
+        T: Transition<DynData, Ctx> + TransitionEvent + 'static,
+        T::Event: EventOf<E>,
+    {
+        self.push_transition(Box::new(transition))
     }
 
-    pub fn build(self) -> Result<Sm<DynData>, SmError<EnterSmEvent>>
+    /// Builds the state machine that processes events of the enum `E`. In addition to the
+    /// checks of `SmBuilder::build`, all transitions, including the ones of nested state
+    /// machines, must be triggered by events of the enum or by internal events.
+    pub fn build(self) -> Result<Sm<DynData, E, Ctx>, Vec<BuildError>>
     where
-        DynData: Cast<Sm<DynData>>,
+        Ctx: Default,
     {
-        Sm::new(self.vertexes, self.transitions)
+        self.build_with_ctx(Ctx::default())
     }
 
-    fn find_vertex_by_data_tid(&self, tid: TypeId) -> Option<usize>
+    /// Builds the state machine with the context, see `build`.
+    pub fn build_with_ctx(self, ctx: Ctx) -> Result<Sm<DynData, E, Ctx>, Vec<BuildError>> {
+        let mut errors = self.errors();
+        errors.extend(self.event_errors(&E::event_types()));
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.start(ctx).map(Sm::with_events)
+    }
+
+    /// Builds the state machine with the context, that is made from the queue of the state
+    /// machine, see `queue`.
+    pub fn build_with_queue<F>(self, ctx: F) -> Result<Sm<DynData, E, Ctx>, Vec<BuildError>>
     where
-        DynData: Cast<Sm<DynData>>,
+        F: FnOnce(EventQueue) -> Ctx,
     {
-        self.vertexes
-            .iter()
-            .enumerate()
-            .find(|(_, x)| x.data_tid() == tid)
-            .map(|(x, _)| x)
+        let ctx = ctx(self.queue.clone());
+        self.build_with_ctx(ctx)
+    }
+}
+
+impl<DynData, Ctx, E> SmBuilder<DynData, Ctx, E>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    pub fn register_vertex(mut self, vertex: Vertex<DynData, Ctx>) -> Self {
+        self.vertexes.push(vertex);
+        self
+    }
+    fn push_transition(mut self, transition: Box<dyn Transition<DynData, Ctx>>) -> Self {
+        self.transitions
+            .entry(transition.input_tid())
+            .or_default()
+            .push(transition);
+        self
+    }
+
+    /// Returns the queue, that actions post events to. Its clones refer to the same queue, so
+    /// they can be captured by actions, or passed to the context with `build_with_queue`. Posted
+    /// events are processed by the state machine after the current step, see
+    /// `Sm::process_queue`.
+    ///
+    /// Only the outermost state machine processes the queue, actions of nested state machines
+    /// post events through the context or the queue of the outermost one.
+    pub fn queue(&self) -> &EventQueue {
+        &self.queue
+    }
+
+    /// Starts the validated state machine with the context.
+    fn start(self, ctx: Ctx) -> Result<Sm<DynData, Event, Ctx>, Vec<BuildError>> {
+        Sm::with_queue(self.vertexes, self.transitions, ctx, self.queue)
+            .map_err(|e| vec![BuildError::Init(e)])
+    }
+
+    /// Returns errors for transitions that are triggered by events not from `events`.
+    fn event_errors(&self, events: &[TypeInfo]) -> Vec<BuildError> {
+        let internal = [
+            TypeInfo::of::<EnterSmEvent>(),
+            TypeInfo::of::<CompletionEvent>(),
+        ];
+        let mut errors = vec![];
+        for transition in self.ordered_transitions() {
+            let event = transition.event_type();
+            if !events.contains(&event) && !internal.contains(&event) {
+                errors.push(BuildError::UnexpectedEvent(event.name));
+            }
+        }
+        for vertex in &self.vertexes {
+            for region in vertex.regions() {
+                let nested = region.machine().event_errors(events);
+                if !nested.is_empty() {
+                    errors.push(BuildError::InvalidSubmachine {
+                        state: vertex.data_type_name(),
+                        errors: nested,
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    /// Checks the state machine and the nested state machines without building them.
+    pub fn validate(&self) -> Result<(), Vec<BuildError>> {
+        let errors = self.errors();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// Analyzes the transition graph of the state machine: which states are reachable, which
+    /// states are sinks or dead ends, and how states form strongly connected components.
+    ///
+    /// Transitions to or from unregistered vertexes are ignored, duplicated vertexes are
+    /// reported once.
+    pub fn analyze(&self) -> Analysis {
+        Analysis::new(&self.graph())
+    }
+
+    /// Renders the state machine in the Graphviz DOT language, see `Sm::to_dot`.
+    pub fn to_dot(&self) -> String {
+        self.diagram().to_dot()
+    }
+
+    /// Renders the state machine as a PlantUML state diagram, see `Sm::to_plantuml`.
+    pub fn to_plantuml(&self) -> String {
+        self.diagram().to_plantuml()
+    }
+
+    /// Renders the state machine as a Mermaid state diagram, see `Sm::to_mermaid`.
+    pub fn to_mermaid(&self) -> String {
+        self.diagram().to_mermaid()
+    }
+
+    /// Renders the topology of the state machine as a SCXML document, see `Sm::to_scxml`.
+    pub fn to_scxml(&self) -> String {
+        self.diagram().to_scxml()
+    }
+
+    pub(crate) fn diagram(&self) -> Diagram {
+        Diagram::new(&self.vertexes, &self.transitions, None)
+    }
+
+    /// Returns types of events that the state machine or its nested state machines handle or
+    /// defer, except `EnterSmEvent` and `CompletionEvent`.
+    pub(crate) fn event_types(&self) -> Vec<TypeInfo> {
+        let internal = [
+            TypeId::of::<EnterSmEvent>(),
+            TypeId::of::<CompletionEvent>(),
+        ];
+        let mut events: Vec<TypeInfo> = self
+            .transitions
+            .values()
+            .flatten()
+            .map(|t| t.event_type())
+            .chain(
+                self.vertexes
+                    .iter()
+                    .flat_map(|v| v.deferred().iter().copied()),
+            )
+            .chain(
+                self.vertexes
+                    .iter()
+                    .flat_map(|v| v.regions())
+                    .flat_map(|r| r.machine().event_types()),
+            )
+            .filter(|e| !internal.contains(&e.tid))
+            .collect();
+        events.sort_by_key(|e| e.name);
+        events.dedup();
+        events
+    }
+}
+
+impl<DynData, Ctx, E> Validate for SmBuilder<DynData, Ctx, E>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    type Vertex = Vertex<DynData, Ctx>;
+    type Transition = dyn Transition<DynData, Ctx>;
+
+    fn vertexes(&self) -> &[Self::Vertex] {
+        &self.vertexes
+    }
+
+    fn transitions(&self) -> &BTreeMap<TypeId, Vec<Box<Self::Transition>>> {
+        &self.transitions
+    }
+
+    fn has_output(&self, transition: &Self::Transition, output: TypeInfo) -> bool {
+        match transition.kind() {
+            // Local transitions end in the nested state machine of the source state.
+            TransitionKind::Local => self
+                .find_vertex_by_data_tid(transition.input_type().tid)
+                .into_iter()
+                .flat_map(|i| self.vertexes[i].regions())
+                .any(|r| r.machine().find_vertex_by_data_tid(output.tid).is_some()),
+            _ => self.find_vertex_by_data_tid(output.tid).is_some(),
+        }
+    }
+
+    fn vertex_errors(&self, vertex: &Self::Vertex, errors: &mut Vec<BuildError>) {
+        if let Vertex::OrthogonalState(o) = vertex {
+            for event in o.shared_events() {
+                errors.push(BuildError::SharedEvent {
+                    state: vertex.data_type_name(),
+                    event,
+                });
+            }
+        }
+        for region in vertex.regions() {
+            let nested = region.machine().errors();
+            if !nested.is_empty() {
+                errors.push(BuildError::InvalidSubmachine {
+                    state: vertex.data_type_name(),
+                    errors: nested,
+                });
+            }
+        }
+    }
+}
+
+/// Problem found in the state machine description by `SmBuilder::build`. Vertexes are reported
+/// by names of their data types.
+#[derive(Debug, PartialEq, Clone)]
+pub enum BuildError {
+    /// Transition starts in the vertex that is not registered.
+    MissingInputVertex(&'static str),
+    /// Transition ends in the vertex that is not registered.
+    MissingOutputVertex(&'static str),
+    /// Several vertexes with the same data type are registered.
+    DuplicateVertex(&'static str),
+    /// State cannot be reached from the initial pseudo-state.
+    UnreachableState(&'static str),
+    /// State has no outgoing transitions and it is not final.
+    NoOutgoingTransitions(&'static str),
+    /// Initial pseudo-state has more than one transition on `EnterSmEvent`.
+    MultipleInitialTransitions(usize),
+    /// State machine has more than one history pseudo-state.
+    MultipleHistoryVertexes(usize),
+    /// History pseudo-state has more than one default transition on `EnterSmEvent`.
+    MultipleDefaultHistoryTransitions(&'static str, usize),
+    /// Transition starts in the terminate pseudo-state.
+    TransitionFromTerminate(&'static str),
+    /// Transition starts in the final state.
+    TransitionFromFinal(&'static str),
+    /// Nested state machine of the composite state is invalid.
+    InvalidSubmachine {
+        state: &'static str,
+        errors: Vec<BuildError>,
+    },
+    /// Transition is triggered by the event that is not in the event enum, see
+    /// `SmBuilder::with_events`.
+    UnexpectedEvent(&'static str),
+    /// Event is handled or deferred by several regions of the orthogonal state, but it is not
+    /// registered with `OrthogonalVertex::broadcast`.
+    SharedEvent {
+        state: &'static str,
+        event: &'static str,
+    },
+    /// Outgoing segment of the junction or choice pseudo-state, given by the first name, starts
+    /// in the vertex given by the second name instead of the pseudo-state.
+    WrongSegmentInput(&'static str, &'static str),
+    /// Outgoing segment of the junction or choice pseudo-state, given by the first name, is
+    /// triggered by the event given by the second name instead of `EnterSmEvent`.
+    WrongSegmentEvent(&'static str, &'static str),
+    /// Outgoing segment of the junction or choice pseudo-state is not an external transition.
+    WrongSegmentKind(&'static str, TransitionKind),
+    /// Outgoing segment of the junction or choice pseudo-state has guards of its own, e.g. the
+    /// `else` segment is guarded. The source state is already left when the segment is
+    /// performed, so it must not be rejected.
+    GuardedSegment(&'static str),
+    /// State machine failed on the initial transition.
+    Init(SmError<EnterSmEvent>),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{BuildError, EnterSmEvent, SmBuilder, Vertex};
+    use std::any::Any;
+
+    #[test]
+    fn test_transition_from_terminate() {
+        use crate::state::TerminatePseudoState;
+
+        struct SomeState;
+
+        let errors = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<SomeState>::new().to_final_vertex())
+            .register_vertex(Vertex::terminate())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| {
+                TerminatePseudoState
+            }))
+            .transition(ftrans(|_: TerminatePseudoState, _: i32| SomeState))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![BuildError::TransitionFromTerminate(std::any::type_name::<
+                TerminatePseudoState,
+            >())]
+        );
+    }
+
+    #[test]
+    fn test_build_errors() {
+        use std::any::type_name;
+
+        struct A;
+        struct B;
+        struct Dead;
+        struct Missing;
+        struct Inner;
+
+        let errors = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<A>::new().to_vertex())
+            .register_vertex(SimpleVertex::<A>::new().to_vertex())
+            .register_vertex(SimpleVertex::<B>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Dead>::new().to_final_vertex())
+            .register_vertex(SimpleVertex::<Inner>::new().to_submachine_vertex(|| {
+                SmBuilder::new().transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| A))
+            }))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| A))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| B))
+            .transition(ftrans(|_: A, _: i32| Missing))
+            .transition(ftrans(|_: A, _: u32| Inner))
+            .transition(ftrans(|_: Missing, _: i32| A))
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                BuildError::DuplicateVertex(type_name::<A>()),
+                BuildError::MissingOutputVertex(type_name::<Missing>()),
+                BuildError::MissingInputVertex(type_name::<Missing>()),
+                BuildError::MultipleInitialTransitions(2),
+                BuildError::NoOutgoingTransitions(type_name::<B>()),
+                BuildError::UnreachableState(type_name::<Dead>()),
+                BuildError::InvalidSubmachine {
+                    state: type_name::<Inner>(),
+                    errors: vec![BuildError::MissingOutputVertex(type_name::<A>())],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_errors() {
+        use crate::state::{DeepHistoryPseudoState, ShallowHistoryPseudoState};
+        use std::any::type_name;
+
+        struct A;
+        struct B;
+
+        let errors = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<A>::new().to_vertex())
+            .register_vertex(SimpleVertex::<B>::new().to_final_vertex())
+            .register_vertex(Vertex::shallow_history())
+            .register_vertex(Vertex::deep_history())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| A))
+            .transition(ftrans(|_: ShallowHistoryPseudoState, _: EnterSmEvent| A))
+            .transition(ftrans(|_: ShallowHistoryPseudoState, _: EnterSmEvent| B))
+            .transition(ftrans(|_: DeepHistoryPseudoState, _: EnterSmEvent| B))
+            .transition(ftrans(|_: A, _: i32| B))
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                BuildError::MultipleHistoryVertexes(2),
+                BuildError::MultipleDefaultHistoryTransitions(
+                    type_name::<ShallowHistoryPseudoState>(),
+                    2
+                ),
+            ]
+        );
     }
 }
@@ -0,0 +1,241 @@
+use crate::event::EnterSmEvent;
+use crate::junction::Compound;
+use crate::sm::analysis::Graph;
+use crate::transition::{TransitionKind, TypeInfo};
+use crate::BuildError;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::TypeId;
+
+/// Vertex as seen by the checks of `Validate`.
+pub(crate) trait VertexInfo {
+    fn data_type(&self) -> TypeInfo;
+
+    fn is_final(&self) -> bool;
+
+    fn is_terminate(&self) -> bool;
+
+    fn is_history(&self) -> bool;
+
+    /// Returns `true` if the vertex may have no outgoing transitions.
+    fn may_stay(&self) -> bool;
+}
+
+/// Transition as seen by the checks of `Validate`.
+pub(crate) trait TransitionInfo {
+    fn input_type(&self) -> TypeInfo;
+
+    fn output_types(&self) -> Vec<TypeInfo>;
+
+    fn event_type(&self) -> TypeInfo;
+
+    /// Describes the junction or choice pseudo-state the compound transition passes.
+    fn compound(&self) -> Option<Compound<'_>> {
+        None
+    }
+}
+
+/// Checks that outgoing segments of the junction or choice pseudo-state cannot fail, see
+/// `junction`.
+fn segment_errors(compound: &Compound) -> Vec<BuildError> {
+    let pseudo_state = compound.pseudo_state.name;
+    let mut errors = vec![];
+    for segment in &compound.segments {
+        if segment.input != compound.pseudo_state {
+            errors.push(BuildError::WrongSegmentInput(
+                pseudo_state,
+                segment.input.name,
+            ));
+        }
+        if segment.event != TypeInfo::of::<EnterSmEvent>() {
+            errors.push(BuildError::WrongSegmentEvent(
+                pseudo_state,
+                segment.event.name,
+            ));
+        }
+        if segment.kind != TransitionKind::External {
+            errors.push(BuildError::WrongSegmentKind(pseudo_state, segment.kind));
+        }
+        if segment.guarded {
+            errors.push(BuildError::GuardedSegment(pseudo_state));
+        }
+    }
+    errors
+}
+
+/// Checks of the state machine description shared by `SmBuilder` and `AsyncSmBuilder`.
+pub(crate) trait Validate {
+    type Vertex: VertexInfo;
+    type Transition: TransitionInfo + ?Sized;
+
+    /// Returns vertexes in the order of registration, the initial pseudo-state is the first one.
+    fn vertexes(&self) -> &[Self::Vertex];
+
+    /// Returns transitions by the type id of their input vertexes.
+    fn transitions(&self) -> &BTreeMap<TypeId, Vec<Box<Self::Transition>>>;
+
+    /// Returns `true` if the `output` vertex of the transition is registered.
+    fn has_output(&self, _transition: &Self::Transition, output: TypeInfo) -> bool {
+        self.find_vertex_by_data_tid(output.tid).is_some()
+    }
+
+    /// Reports errors specific to the builder, for every vertex except duplicates.
+    fn vertex_errors(&self, _vertex: &Self::Vertex, _errors: &mut Vec<BuildError>) {}
+
+    fn errors(&self) -> Vec<BuildError> {
+        let vertexes = self.vertexes();
+        let mut errors = vec![];
+
+        let mut seen = BTreeSet::new();
+        let mut duplicates = BTreeSet::new();
+        for vertex in vertexes {
+            let data = vertex.data_type();
+            if !seen.insert(data.tid) && duplicates.insert(data.tid) {
+                errors.push(BuildError::DuplicateVertex(data.name));
+            }
+        }
+
+        for transition in self.ordered_transitions() {
+            let input = transition.input_type();
+            match self
+                .find_vertex_by_data_tid(input.tid)
+                .map(|i| &vertexes[i])
+            {
+                None => errors.push(BuildError::MissingInputVertex(input.name)),
+                Some(v) if v.is_final() => errors.push(BuildError::TransitionFromFinal(input.name)),
+                Some(v) if v.is_terminate() => {
+                    errors.push(BuildError::TransitionFromTerminate(input.name))
+                }
+                Some(_) => {}
+            }
+            for output in transition.output_types() {
+                if !self.has_output(transition, output) {
+                    errors.push(BuildError::MissingOutputVertex(output.name));
+                }
+            }
+            if let Some(compound) = transition.compound() {
+                errors.extend(segment_errors(&compound));
+            }
+        }
+
+        let initial_transitions = self
+            .transitions_from(0)
+            .filter(|t| t.event_type().tid == TypeId::of::<EnterSmEvent>())
+            .count();
+        if initial_transitions > 1 {
+            errors.push(BuildError::MultipleInitialTransitions(initial_transitions));
+        }
+
+        let histories: Vec<usize> = (0..vertexes.len())
+            .filter(|&i| vertexes[i].is_history())
+            .collect();
+        if histories.len() > 1 {
+            errors.push(BuildError::MultipleHistoryVertexes(histories.len()));
+        }
+        for &i in &histories {
+            let default_transitions = self
+                .transitions_from(i)
+                .filter(|t| t.event_type().tid == TypeId::of::<EnterSmEvent>())
+                .count();
+            if default_transitions > 1 {
+                errors.push(BuildError::MultipleDefaultHistoryTransitions(
+                    vertexes[i].data_type().name,
+                    default_transitions,
+                ));
+            }
+        }
+
+        let graph = self.graph();
+        let reachable = graph.reachable();
+        // Nodes of the graph are vertexes without duplicates, in the same order.
+        let mut node = 0;
+        for (i, vertex) in vertexes.iter().enumerate() {
+            let data = vertex.data_type();
+            if self.find_vertex_by_data_tid(data.tid) != Some(i) {
+                // Duplicates are already reported.
+                continue;
+            }
+            if !reachable.contains(&node) {
+                errors.push(BuildError::UnreachableState(data.name));
+            }
+            if graph.sinks.contains(&node) {
+                errors.push(BuildError::NoOutgoingTransitions(data.name));
+            }
+            node += 1;
+            self.vertex_errors(vertex, &mut errors);
+        }
+
+        errors
+    }
+
+    /// Returns the transition graph over vertexes without duplicates.
+    fn graph(&self) -> Graph {
+        let all = self.vertexes();
+        let vertexes: Vec<usize> = (0..all.len())
+            .filter(|&i| self.find_vertex_by_data_tid(all[i].data_type().tid) == Some(i))
+            .collect();
+        let node = |i: usize| vertexes.iter().position(|&v| v == i);
+        let edges = vertexes
+            .iter()
+            .map(|&i| {
+                let mut edges: Vec<usize> = self
+                    .transitions_from(i)
+                    .flat_map(|t| t.output_types())
+                    .filter_map(|output| self.find_vertex_by_data_tid(output.tid))
+                    .filter_map(node)
+                    .collect();
+                edges.sort_unstable();
+                edges.dedup();
+                edges
+            })
+            .collect();
+        let nodes_where =
+            |f: &dyn Fn(usize) -> bool| (0..vertexes.len()).filter(|&n| f(vertexes[n])).collect();
+        Graph {
+            names: vertexes.iter().map(|&i| all[i].data_type().name).collect(),
+            edges,
+            entries: nodes_where(&|i| {
+                all[i].data_type().tid == all[0].data_type().tid || all[i].is_history()
+            }),
+            exits: nodes_where(&|i| all[i].is_final() || all[i].is_terminate()),
+            sinks: nodes_where(&|i| {
+                !all[i].may_stay() && self.transitions_from(i).next().is_none()
+            }),
+        }
+    }
+
+    fn transitions_from(&self, vertex: usize) -> impl Iterator<Item = &Self::Transition> {
+        self.transitions()
+            .get(&self.vertexes()[vertex].data_type().tid)
+            .into_iter()
+            .flatten()
+            .map(|t| t.as_ref())
+    }
+
+    /// Returns transitions in the order of their input vertexes, so that errors are reported in
+    /// the stable order.
+    fn ordered_transitions(&self) -> Vec<&Self::Transition> {
+        let vertexes = self.vertexes();
+        let mut transitions: Vec<_> = (0..vertexes.len())
+            .filter(|&i| self.find_vertex_by_data_tid(vertexes[i].data_type().tid) == Some(i))
+            .flat_map(|i| self.transitions_from(i))
+            .collect();
+        let mut rest: Vec<_> = self
+            .transitions()
+            .iter()
+            .filter(|(tid, _)| self.find_vertex_by_data_tid(**tid).is_none())
+            .flat_map(|(_, ts)| ts.iter().map(|t| t.as_ref()))
+            .collect();
+        rest.sort_by_key(|t| t.input_type().name);
+        transitions.extend(rest);
+        transitions
+    }
+
+    fn find_vertex_by_data_tid(&self, tid: TypeId) -> Option<usize> {
+        self.vertexes()
+            .iter()
+            .position(|x| x.data_type().tid == tid)
+    }
+}
@@ -1,5 +1,12 @@
-use crate::vertex::{StateTrait, Vertex};
-use std::any::{Any, TypeId};
+use crate::event::Event;
+use crate::transition::TypeInfo;
+use crate::vertex::{PseudoStateKind, StateTrait, Vertex};
+use crate::{BuildError, Sm, SmBuilder, SmError};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
 
 pub trait Cast<From: 'static>: Any {
     fn upcast(from: Box<From>) -> Box<Self>;
@@ -15,7 +22,7 @@ pub trait Cast<From: 'static>: Any {
             panic!("Must be guaranteed by the caller.")
         }
     }
-    fn downcast_ref(self: &Self) -> Option<&From> {
+    fn downcast_ref(&self) -> Option<&From> {
         if self.concrete_tid() == TypeId::of::<From>() {
             unsafe {
                 let rf = &*(self as *const Self as *const From);
@@ -48,28 +55,42 @@ impl<T: Any> Cast<T> for dyn Any {
 #[derive(Debug, PartialEq)]
 pub struct InitialPseudoState;
 
-pub struct SimpleVertex<T> {
+#[derive(Debug, PartialEq)]
+pub struct TerminatePseudoState;
+
+#[derive(Debug, PartialEq)]
+pub struct ShallowHistoryPseudoState;
+
+#[derive(Debug, PartialEq)]
+pub struct DeepHistoryPseudoState;
+
+type Hook<T, Ctx> = Box<dyn Fn(&T, &mut Ctx)>;
+
+/// State with the data of type `T`. `Ctx` is the context of the state machine, that is passed to
+/// entry and exit actions, see `with_entry_ctx`.
+pub struct SimpleVertex<T, Ctx = ()> {
     data: Option<Box<T>>,
-    entry: Box<dyn for<'a> Fn(&'a T)>,
-    exit: Box<dyn for<'a> Fn(&'a T)>,
+    entry: Option<Hook<T, Ctx>>,
+    exit: Option<Hook<T, Ctx>>,
+    deferred: Vec<TypeInfo>,
 }
 
-fn do_nothing<T>(_: &T) {}
-
-impl<T: 'static> SimpleVertex<T> {
-    pub fn new() -> SimpleVertex<T> {
+impl<T: 'static, Ctx> SimpleVertex<T, Ctx> {
+    pub fn new() -> SimpleVertex<T, Ctx> {
         SimpleVertex {
             data: None,
-            entry: Box::new(do_nothing),
-            exit: Box::new(do_nothing),
+            entry: None,
+            exit: None,
+            deferred: vec![],
         }
     }
 
-    pub fn with_data(data: T) -> SimpleVertex<T> {
+    pub fn with_data(data: T) -> SimpleVertex<T, Ctx> {
         SimpleVertex {
             data: Some(Box::new(data)),
-            entry: Box::new(do_nothing),
-            exit: Box::new(do_nothing),
+            entry: None,
+            exit: None,
+            deferred: vec![],
         }
     }
 
@@ -80,52 +101,99 @@ impl<T: 'static> SimpleVertex<T> {
     }
 }
 
-impl<T> SimpleVertex<T> {
-    pub fn with_entry(self, entry: impl for<'a> Fn(&'a T) + 'static) -> SimpleVertex<T> {
+impl<T: 'static, Ctx> Default for SimpleVertex<T, Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, Ctx> SimpleVertex<T, Ctx> {
+    pub fn with_entry(self, entry: impl for<'a> Fn(&'a T) + 'static) -> SimpleVertex<T, Ctx> {
+        self.with_entry_ctx(move |data, _| entry(data))
+    }
+
+    /// Sets the entry action, that takes the context of the state machine too.
+    pub fn with_entry_ctx(self, entry: impl Fn(&T, &mut Ctx) + 'static) -> SimpleVertex<T, Ctx> {
         SimpleVertex {
-            entry: Box::new(entry),
+            entry: Some(Box::new(entry)),
             ..self
         }
     }
 }
 
-impl<T> SimpleVertex<T> {
-    pub fn with_exit(self, exit: impl for<'a> Fn(&'a T) + 'static) -> SimpleVertex<T> {
+impl<T, Ctx> SimpleVertex<T, Ctx> {
+    pub fn with_exit(self, exit: impl for<'a> Fn(&'a T) + 'static) -> SimpleVertex<T, Ctx> {
+        self.with_exit_ctx(move |data, _| exit(data))
+    }
+
+    /// Sets the exit action, that takes the context of the state machine too.
+    pub fn with_exit_ctx(self, exit: impl Fn(&T, &mut Ctx) + 'static) -> SimpleVertex<T, Ctx> {
         SimpleVertex {
-            exit: Box::new(exit),
+            exit: Some(Box::new(exit)),
             ..self
         }
     }
 }
 
-impl<T: 'static> SimpleVertex<T> {
-    pub fn to_vertex<DynData: Cast<T> + ?Sized>(self) -> Vertex<DynData> {
+impl<T, Ctx> SimpleVertex<T, Ctx> {
+    /// Defers events of type `E` in this state. If such an event is not handled by any
+    /// transition, the state machine keeps it and processes it again after the next external
+    /// transition, see `Sm::process_boxed`.
+    pub fn defer<E: 'static>(mut self) -> SimpleVertex<T, Ctx> {
+        self.deferred.push(TypeInfo::of::<E>());
+        self
+    }
+}
+
+impl<T: 'static, Ctx: 'static> SimpleVertex<T, Ctx> {
+    pub fn to_vertex<DynData: Cast<T> + ?Sized>(self) -> Vertex<DynData, Ctx> {
         Vertex::State(Box::new(self))
     }
+
+    /// Makes a final state from this vertex.
+    pub fn to_final_vertex<DynData: Cast<T> + ?Sized>(self) -> Vertex<DynData, Ctx> {
+        Vertex::FinalState(Box::new(self))
+    }
+
+    /// Makes a composite state from this vertex. `machine` describes the nested state machine,
+    /// it is built every time the composite state is entered.
+    pub fn to_submachine_vertex<DynData>(
+        self,
+        machine: impl Fn() -> SmBuilder<DynData, Ctx> + 'static,
+    ) -> Vertex<DynData, Ctx>
+    where
+        DynData: Cast<T> + ?Sized + 'static,
+    {
+        Vertex::SubMachineState(SubMachineVertex {
+            state: Box::new(self),
+            region: Region::new(machine),
+        })
+    }
 }
 
-impl<T, DynData> StateTrait<DynData> for SimpleVertex<T>
+impl<T, DynData, Ctx> StateTrait<DynData, Ctx> for SimpleVertex<T, Ctx>
 where
     T: 'static,
     DynData: Cast<T> + ?Sized,
 {
-    fn entry(&self) {
-        (self.entry)(
-            &self
-                .data
-                .as_ref()
-                .expect("It must be guaranteed by the caller")
-                .as_ref(),
-        );
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
+        let data = self
+            .data
+            .as_ref()
+            .expect("It must be guaranteed by the caller");
+        if let Some(entry) = &self.entry {
+            entry(data, ctx);
+        }
+        Ok(())
     }
-    fn exit(&self) {
-        (self.exit)(
-            &self
-                .data
-                .as_ref()
-                .expect("It must be guaranteed by the caller")
-                .as_ref(),
-        );
+    fn exit(&mut self, ctx: &mut Ctx) {
+        let data = self
+            .data
+            .as_ref()
+            .expect("It must be guaranteed by the caller");
+        if let Some(exit) = &self.exit {
+            exit(data, ctx);
+        }
     }
     fn get_data(&mut self) -> Box<DynData> {
         self.data
@@ -141,10 +209,663 @@ where
             .expect("This method must be called only once.")
     }
 
+    fn get_data_as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        let data = self
+            .data
+            .as_deref_mut()
+            .expect("This method must be called only once.");
+        Some(data)
+    }
+
     fn set_data(&mut self, data: Box<DynData>) {
         self.data = Some(data.downcast())
     }
     fn data_tid(&self) -> TypeId {
         TypeId::of::<T>()
     }
+    fn data_type_name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+    fn has_entry(&self) -> bool {
+        self.entry.is_some()
+    }
+    fn has_exit(&self) -> bool {
+        self.exit.is_some()
+    }
+    fn deferred(&self) -> &[TypeInfo] {
+        &self.deferred
+    }
+}
+
+/// Composite state, i.e. a state with the nested state machine inside.
+///
+/// Events that cannot be handled by transitions from the composite state itself are passed to
+/// the nested state machine. If the nested state machine contains a history pseudo-state, it is
+/// kept after exit and resumed when the composite state is entered again.
+pub struct SubMachineVertex<DynData: ?Sized, Ctx = ()> {
+    pub(crate) state: Box<dyn StateTrait<DynData, Ctx>>,
+    pub(crate) region: Region<DynData, Ctx>,
+}
+
+impl<DynData, Ctx> SubMachineVertex<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    /// Enters the state restoring the whole nested configuration, if any.
+    pub(crate) fn entry_deep(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
+        self.state.entry(ctx)?;
+        let errors = self.region.entry_deep(ctx).err().unwrap_or_default();
+        started(self.state.data_type_name(), errors)
+    }
+}
+
+impl<DynData, Ctx> StateTrait<DynData, Ctx> for SubMachineVertex<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
+        self.state.entry(ctx)?;
+        let errors = self.region.entry(ctx).err().unwrap_or_default();
+        started(self.state.data_type_name(), errors)
+    }
+    fn exit(&mut self, ctx: &mut Ctx) {
+        self.region.exit(ctx);
+        self.state.exit(ctx);
+    }
+    fn get_data(&mut self) -> Box<DynData> {
+        self.region.leave();
+        self.state.get_data()
+    }
+    fn get_data_as_ref(&self) -> &DynData {
+        self.state.get_data_as_ref()
+    }
+    fn get_data_as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        self.state.get_data_as_any_mut()
+    }
+    fn set_data(&mut self, data: Box<DynData>) {
+        self.state.set_data(data)
+    }
+    fn data_tid(&self) -> TypeId {
+        self.state.data_tid()
+    }
+    fn data_type_name(&self) -> &'static str {
+        self.state.data_type_name()
+    }
+    fn has_entry(&self) -> bool {
+        self.state.has_entry()
+    }
+    fn has_exit(&self) -> bool {
+        self.state.has_exit()
+    }
+    fn deferred(&self) -> &[TypeInfo] {
+        self.state.deferred()
+    }
+}
+
+/// Composite state with several orthogonal regions. Each region is a nested state machine, and
+/// all of them are active at the same time while the state is active.
+///
+/// Events that cannot be handled by transitions from the state itself are offered to every
+/// region. Because events cannot be copied in general, an event that is handled or deferred by
+/// several regions must have its type registered with `broadcast`, so that each region receives
+/// its own copy; otherwise `SmBuilder::build` reports `BuildError::SharedEvent`. The state
+/// receives `CompletionEvent` when all regions reach their final states.
+pub struct OrthogonalVertex<DynData: ?Sized, Ctx = ()> {
+    pub(crate) state: Box<dyn StateTrait<DynData, Ctx>>,
+    pub(crate) regions: Vec<Region<DynData, Ctx>>,
+    broadcast: BTreeMap<TypeId, fn(&Event) -> Event>,
+}
+
+impl<DynData, Ctx> OrthogonalVertex<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    pub fn new<T>(state: SimpleVertex<T, Ctx>) -> Self
+    where
+        T: 'static,
+        DynData: Cast<T>,
+    {
+        OrthogonalVertex {
+            state: Box::new(state),
+            regions: vec![],
+            broadcast: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the region. `machine` describes the nested state machine of the region, it is
+    /// built every time the state is entered.
+    pub fn region(mut self, machine: impl Fn() -> SmBuilder<DynData, Ctx> + 'static) -> Self {
+        self.regions.push(Region::new(machine));
+        self
+    }
+
+    /// Makes events of type `E` to be delivered to every region.
+    pub fn broadcast<E: Any + Clone>(mut self) -> Self {
+        fn clone<E: Any + Clone>(event: &Event) -> Event {
+            Box::new(
+                event
+                    .downcast_ref::<E>()
+                    .expect("It must be guaranteed by the caller")
+                    .clone(),
+            )
+        }
+        self.broadcast.insert(TypeId::of::<E>(), clone::<E>);
+        self
+    }
+
+    pub fn to_vertex(self) -> Vertex<DynData, Ctx> {
+        Vertex::OrthogonalState(self)
+    }
+
+    /// Enters the state restoring the whole nested configuration of every region, if any.
+    pub(crate) fn entry_deep(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
+        self.state.entry(ctx)?;
+        let mut errors = vec![];
+        for region in &mut self.regions {
+            errors.extend(region.entry_deep(ctx).err().unwrap_or_default());
+        }
+        started(self.state.data_type_name(), errors)
+    }
+
+    /// Offers the event to every active region. Only events registered with `broadcast` can be
+    /// handled by several regions, it is checked by `SmBuilder::build`, so the event that is not
+    /// registered is passed further until a region handles it.
+    pub(crate) fn process_in(&mut self, event: Event, ctx: &mut Ctx) -> Result<(), SmError<Event>> {
+        let active = self.regions.iter_mut().filter_map(|r| r.sm.as_mut());
+        if let Some(clone) = self.broadcast.get(&(*event).type_id()) {
+            let mut handled = false;
+            for sm in active {
+                match sm.process_in(clone(&event), ctx) {
+                    Ok(()) => handled = true,
+                    Err(err) => {
+                        err.unhandled()?;
+                    }
+                }
+            }
+            return match handled {
+                true => Ok(()),
+                false => Err(SmError::NoTransitionSatisfyingEvent(event)),
+            };
+        }
+        let mut err = SmError::NoTransitionsFromThisVertex(event);
+        for sm in active {
+            err = match sm.process_in(err.unhandled()?, ctx) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+        }
+        Err(err)
+    }
+
+    /// Returns events that are handled by several regions, but not registered with
+    /// `broadcast`.
+    pub(crate) fn shared_events(&self) -> Vec<&'static str> {
+        let mut seen = BTreeMap::new();
+        for region in &self.regions {
+            for event in region.machine().event_types() {
+                *seen.entry((event.name, event.tid)).or_insert(0) += 1;
+            }
+        }
+        seen.into_iter()
+            .filter(|((_, tid), regions)| *regions > 1 && !self.broadcast.contains_key(tid))
+            .map(|((name, _), _)| name)
+            .collect()
+    }
+
+    /// Returns active state machines of the regions.
+    pub(crate) fn machines(&self) -> impl Iterator<Item = &Sm<DynData, Event, Ctx>> {
+        self.regions.iter().filter_map(|r| r.sm.as_ref())
+    }
+}
+
+impl<DynData, Ctx> StateTrait<DynData, Ctx> for OrthogonalVertex<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
+        self.state.entry(ctx)?;
+        let mut errors = vec![];
+        for region in &mut self.regions {
+            errors.extend(region.entry(ctx).err().unwrap_or_default());
+        }
+        started(self.state.data_type_name(), errors)
+    }
+    fn exit(&mut self, ctx: &mut Ctx) {
+        for region in self.regions.iter_mut().rev() {
+            region.exit(ctx);
+        }
+        self.state.exit(ctx);
+    }
+    fn get_data(&mut self) -> Box<DynData> {
+        self.regions.iter_mut().for_each(Region::leave);
+        self.state.get_data()
+    }
+    fn get_data_as_ref(&self) -> &DynData {
+        self.state.get_data_as_ref()
+    }
+    fn get_data_as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        self.state.get_data_as_any_mut()
+    }
+    fn set_data(&mut self, data: Box<DynData>) {
+        self.state.set_data(data)
+    }
+    fn data_tid(&self) -> TypeId {
+        self.state.data_tid()
+    }
+    fn data_type_name(&self) -> &'static str {
+        self.state.data_type_name()
+    }
+    fn has_entry(&self) -> bool {
+        self.state.has_entry()
+    }
+    fn has_exit(&self) -> bool {
+        self.state.has_exit()
+    }
+    fn deferred(&self) -> &[TypeInfo] {
+        self.state.deferred()
+    }
+}
+
+/// Nested state machine of the composite state.
+pub(crate) struct Region<DynData: ?Sized, Ctx> {
+    machine: Box<dyn Fn() -> SmBuilder<DynData, Ctx>>,
+    pub(crate) sm: Option<Sm<DynData, Event, Ctx>>,
+}
+
+impl<DynData, Ctx> Region<DynData, Ctx>
+where
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    pub(crate) fn new(machine: impl Fn() -> SmBuilder<DynData, Ctx> + 'static) -> Self {
+        Region {
+            machine: Box::new(machine),
+            sm: None,
+        }
+    }
+
+    /// Enters the region, resuming the nested state machine if it has history.
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), Vec<BuildError>> {
+        let deep = match self.sm.as_ref().and_then(|sm| sm.history_kind()) {
+            Some(PseudoStateKind::ShallowHistory) => false,
+            Some(PseudoStateKind::DeepHistory) => true,
+            _ => return self.build_sm(ctx),
+        };
+        match self.sm.as_mut() {
+            Some(sm) => sm.resume(deep, ctx).map_err(|e| vec![e]),
+            None => Ok(()),
+        }
+    }
+
+    /// Enters the region restoring the whole nested configuration, if any.
+    fn entry_deep(&mut self, ctx: &mut Ctx) -> Result<(), Vec<BuildError>> {
+        match self.sm.as_mut() {
+            Some(sm) => sm.resume(true, ctx).map_err(|e| vec![e]),
+            None => self.build_sm(ctx),
+        }
+    }
+
+    fn exit(&mut self, ctx: &mut Ctx) {
+        if let Some(sm) = self.sm.as_mut() {
+            sm.exit(ctx);
+        }
+    }
+
+    /// Forgets the nested state machine after the composite state is left, unless it has
+    /// history.
+    fn leave(&mut self) {
+        if self.sm.as_ref().and_then(|sm| sm.history_kind()).is_none() {
+            self.sm = None;
+        }
+    }
+
+    /// Describes the nested state machine.
+    pub(crate) fn machine(&self) -> SmBuilder<DynData, Ctx> {
+        (self.machine)()
+    }
+
+    /// Builds and starts the nested state machine. On failure the region stays inactive.
+    fn build_sm(&mut self, ctx: &mut Ctx) -> Result<(), Vec<BuildError>> {
+        self.sm = Some(self.machine().build_nested(ctx)?);
+        Ok(())
+    }
+}
+
+/// Reports errors of the nested state machines of the composite state `state`, if any.
+fn started(state: &'static str, errors: Vec<BuildError>) -> Result<(), BuildError> {
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(BuildError::InvalidSubmachine { state, errors }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{CompletionEvent, EnterSmEvent, Sm, SmBuilder};
+    use std::any::{Any, TypeId};
+
+    #[test]
+    fn test_submachine() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Active;
+        #[derive(Debug, PartialEq)]
+        struct Inner1;
+        #[derive(Debug, PartialEq)]
+        struct Inner2;
+
+        struct Start;
+        struct Next;
+        struct Stop;
+
+        type Log = Rc<RefCell<Vec<&'static str>>>;
+        fn hook<T>(log: &Log, name: &'static str) -> impl Fn(&T) {
+            let log = log.clone();
+            move |_| log.borrow_mut().push(name)
+        }
+
+        let log = Log::default();
+        let inner_log = log.clone();
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(
+                SimpleVertex::<Active>::new()
+                    .with_entry(hook(&log, "enter Active"))
+                    .with_exit(hook(&log, "exit Active"))
+                    .to_submachine_vertex(move || {
+                        SmBuilder::new()
+                            .register_vertex(
+                                SimpleVertex::<Inner1>::new()
+                                    .with_entry(hook(&inner_log, "enter Inner1"))
+                                    .with_exit(hook(&inner_log, "exit Inner1"))
+                                    .to_vertex(),
+                            )
+                            .register_vertex(
+                                SimpleVertex::<Inner2>::new()
+                                    .with_entry(hook(&inner_log, "enter Inner2"))
+                                    .with_exit(hook(&inner_log, "exit Inner2"))
+                                    .to_final_vertex(),
+                            )
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Inner1))
+                            .transition(ftrans(|_: Inner1, _: Next| Inner2))
+                    }),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Start| Active))
+            .transition(ftrans(|_: Active, _: Stop| Idle))
+            .build()
+            .unwrap();
+
+        assert!(sm.process(Start).is_ok());
+        assert_eq!(*log.borrow(), vec!["enter Active", "enter Inner1"]);
+        assert!(sm.process(Next).is_ok());
+        let path = sm.current_state_path();
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[0].downcast_ref::<Active>(), Some(&Active));
+        assert_eq!(path[1].downcast_ref::<Inner2>(), Some(&Inner2));
+
+        log.borrow_mut().clear();
+        assert!(sm.process(Stop).is_ok());
+        assert_eq!(*log.borrow(), vec!["exit Inner2", "exit Active"]);
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+        assert_eq!(sm.current_state_path().len(), 1);
+    }
+
+    #[test]
+    fn test_submachine_failure() {
+        use crate::guard::GuardedTransition;
+        use crate::{BuildError, SmError};
+        use std::any::type_name;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Active;
+        #[derive(Debug, PartialEq)]
+        struct Inner;
+
+        struct Start;
+        struct Stop;
+
+        let ready = Rc::new(Cell::new(false));
+        let active = |ready: &Rc<Cell<bool>>| {
+            let ready = ready.clone();
+            SimpleVertex::<Active>::new().to_submachine_vertex(move || {
+                let ready = ready.clone();
+                SmBuilder::new()
+                    .register_vertex(SimpleVertex::<Inner>::new().to_final_vertex())
+                    .transition(
+                        GuardedTransition::new()
+                            .guard(move |_: &EnterSmEvent| ready.get())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Inner)),
+                    )
+            })
+        };
+        let failed = BuildError::InvalidSubmachine {
+            state: type_name::<Active>(),
+            errors: vec![BuildError::Init(SmError::NoTransitionSatisfyingEvent(
+                EnterSmEvent,
+            ))],
+        };
+
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(active(&ready))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Start| Active))
+            .transition(ftrans(|_: Active, _: Stop| Idle))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sm.process(Start).map_err(|e| e.map(|_| ())),
+            Err(SmError::SubmachineFailed(Box::new(failed.clone())))
+        );
+        assert_eq!(sm.current_state_concrete(), Some(&Active));
+        assert_eq!(sm.current_state_path().len(), 1);
+        assert!(sm.process(Stop).is_ok());
+
+        ready.set(true);
+        assert!(sm.process(Start).is_ok());
+        assert_eq!(sm.current_state_path().len(), 2);
+
+        ready.set(false);
+        let result = SmBuilder::<dyn Any>::new()
+            .register_vertex(active(&ready))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Active))
+            .build();
+        assert_eq!(
+            result.err(),
+            Some(vec![BuildError::Init(SmError::SubmachineFailed(Box::new(
+                failed
+            )))])
+        );
+    }
+
+    #[test]
+    fn test_local_transition_without_submachine() {
+        use crate::guard::GuardedTransition;
+        use crate::transition::ltrans;
+        use crate::SmError;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq)]
+        struct Idle;
+        #[derive(Debug, PartialEq)]
+        struct Playing;
+        #[derive(Debug, PartialEq)]
+        struct Track1;
+
+        struct Go;
+        struct Restart;
+        struct Stop;
+
+        let restarted = Rc::new(Cell::new(false));
+        let restart = restarted.clone();
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+            .register_vertex(SimpleVertex::<Playing>::new().to_submachine_vertex(|| {
+                SmBuilder::new()
+                    .register_vertex(SimpleVertex::<Track1>::new().to_final_vertex())
+                    .transition(
+                        GuardedTransition::new()
+                            .guard(|_: &EnterSmEvent| false)
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Track1)),
+                    )
+            }))
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+            .transition(ftrans(|_: Idle, _: Go| Playing))
+            .transition(ftrans(|_: Playing, _: Stop| Idle))
+            .transition(ltrans(move |_: &mut Playing, _: Restart| {
+                restart.set(true);
+                Track1
+            }))
+            .build()
+            .unwrap();
+
+        assert!(matches!(sm.process(Go), Err(SmError::SubmachineFailed(_))));
+        assert!(matches!(
+            sm.process(Restart),
+            Err(SmError::NoTransitionSatisfyingEvent(_))
+        ));
+        assert!(!restarted.get());
+        assert_eq!(sm.current_state_concrete(), Some(&Playing));
+        assert_eq!(sm.current_state_path().len(), 1);
+        assert!(sm.process(Stop).is_ok());
+        assert_eq!(sm.current_state_concrete(), Some(&Idle));
+    }
+
+    #[test]
+    fn test_orthogonal_regions() {
+        use crate::state::OrthogonalVertex;
+
+        #[derive(Debug, PartialEq)]
+        struct Device;
+        #[derive(Debug, PartialEq)]
+        struct Off;
+        #[derive(Debug, PartialEq)]
+        struct On;
+        #[derive(Debug, PartialEq)]
+        struct Down;
+        #[derive(Debug, PartialEq)]
+        struct Up;
+        #[derive(Debug, PartialEq)]
+        struct PowerDone;
+        #[derive(Debug, PartialEq)]
+        struct LinkDone;
+        #[derive(Debug, PartialEq)]
+        struct Stopped;
+
+        struct PowerOn;
+        struct LinkUp;
+        #[derive(Clone)]
+        struct Shutdown;
+
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(
+                OrthogonalVertex::new(SimpleVertex::<Device>::new())
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Off>::new().to_vertex())
+                            .register_vertex(SimpleVertex::<On>::new().to_vertex())
+                            .register_vertex(SimpleVertex::<PowerDone>::new().to_final_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Off))
+                            .transition(ftrans(|_: Off, _: PowerOn| On))
+                            .transition(ftrans(|_: On, _: Shutdown| PowerDone))
+                    })
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Down>::new().to_vertex())
+                            .register_vertex(SimpleVertex::<Up>::new().to_vertex())
+                            .register_vertex(SimpleVertex::<LinkDone>::new().to_final_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Down))
+                            .transition(ftrans(|_: Down, _: LinkUp| Up))
+                            .transition(ftrans(|_: Up, _: Shutdown| LinkDone))
+                    })
+                    .broadcast::<Shutdown>()
+                    .to_vertex(),
+            )
+            .register_vertex(SimpleVertex::<Stopped>::new().to_final_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Device))
+            .transition(ftrans(|_: Device, _: CompletionEvent| Stopped))
+            .build()
+            .unwrap();
+
+        let active = |sm: &Sm| -> Vec<TypeId> {
+            sm.active_states()
+                .into_iter()
+                .map(|s| s.type_id())
+                .collect()
+        };
+        assert_eq!(active(&sm), vec![TypeId::of::<Off>(), TypeId::of::<Down>()]);
+        assert!(sm.process(LinkUp).is_ok());
+        assert!(sm.process(PowerOn).is_ok());
+        assert_eq!(active(&sm), vec![TypeId::of::<On>(), TypeId::of::<Up>()]);
+        assert!(sm.process(LinkUp).is_err());
+
+        assert!(sm.process(Shutdown).is_ok());
+        assert_eq!(sm.current_state_concrete(), Some(&Stopped));
+    }
+
+    #[test]
+    fn test_shared_events() {
+        use crate::state::OrthogonalVertex;
+        use crate::BuildError;
+        use std::any::type_name;
+
+        struct Device;
+        struct Off;
+        struct Down;
+
+        struct Shutdown;
+        struct Reset;
+
+        let errors = SmBuilder::<dyn Any>::new()
+            .register_vertex(
+                OrthogonalVertex::new(SimpleVertex::<Device>::new())
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(
+                                SimpleVertex::<Off>::new().defer::<Reset>().to_vertex(),
+                            )
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Off))
+                            .transition(ftrans(|_: Off, _: Shutdown| Off))
+                    })
+                    .region(|| {
+                        SmBuilder::new()
+                            .register_vertex(SimpleVertex::<Down>::new().to_vertex())
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Down))
+                            .transition(ftrans(|_: Down, _: Shutdown| Down))
+                            .transition(ftrans(|_: Down, _: Reset| Down))
+                    })
+                    .to_vertex(),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Device))
+            .build()
+            .unwrap_err();
+        let shared = |event| BuildError::SharedEvent {
+            state: type_name::<Device>(),
+            event,
+        };
+        assert_eq!(
+            errors,
+            vec![
+                shared(type_name::<Reset>()),
+                shared(type_name::<Shutdown>())
+            ]
+        );
+    }
 }
@@ -1,71 +1,240 @@
 use crate::event::Event;
+use crate::junction::Compound;
+use crate::sm::TransitionInfo;
 use crate::state::Cast;
 use crate::vertex::{StateTrait, Vertex};
-use crate::Sm;
-use std::any::{Any, TypeId};
-use std::marker::PhantomData;
-use std::process::Output;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::marker::PhantomData;
 
-pub struct Switch<From, Event, To> {
+/// Effect of the transition. It is called with the event and the context after the source state
+/// is exited and before the target state is entered.
+type Effect<E, Ctx> = Box<dyn Fn(&E, &mut Ctx)>;
+
+pub struct Switch<From, Event, To, Ctx = ()> {
     to: To,
+    effect: Option<Effect<Event, Ctx>>,
     _phantom: PhantomData<(Event, From)>,
 }
 
-impl<F, E, To: Clone> Switch<F, E, To> {
-    pub fn new(to: To) -> Switch<F, E, To> {
+impl<F, E, To: Clone, Ctx> Switch<F, E, To, Ctx> {
+    pub fn new(to: To) -> Switch<F, E, To, Ctx> {
         Switch {
             to,
+            effect: None,
             _phantom: PhantomData,
         }
     }
+
+    /// Sets the effect of the transition, that is called after the source state is exited and
+    /// before the target state is entered.
+    pub fn effect(self, effect: impl Fn(&E) + 'static) -> Switch<F, E, To, Ctx> {
+        self.effect_ctx(move |event, _| effect(event))
+    }
+
+    /// Sets the effect of the transition, that takes the context of the state machine too.
+    pub fn effect_ctx(self, effect: impl Fn(&E, &mut Ctx) + 'static) -> Switch<F, E, To, Ctx> {
+        Switch {
+            effect: Some(Box::new(effect)),
+            ..self
+        }
+    }
 }
 
-impl<DynData, From, E, To> Transition<DynData> for Switch<From, E, To>
+impl<DynData, Ctx, From, E, To> Transition<DynData, Ctx> for Switch<From, E, To, Ctx>
 where
+    Ctx: 'static,
     From: 'static,
     To: Clone + 'static,
-    DynData: ?Sized + Cast<From> + Cast<To> + Cast<Sm<DynData>>,
+    DynData: ?Sized + Cast<From> + Cast<To>,
     E: 'static,
 {
     fn transition(
         &self,
-        from: &mut Vertex<DynData>,
+        from: &mut Vertex<DynData, Ctx>,
         event: Event,
+        ctx: &mut Ctx,
     ) -> Result<TransitionOut<DynData>, TransitionError> {
-        event.downcast::<E>().map_err(|e| TransitionError {
+        let event = event.downcast::<E>().map_err(|e| TransitionError {
             event: e,
             kind: TransitionErrorKind::WrongEvent,
         })?;
-        from.exit();
+        from.exit(ctx);
         from.get_data();
-        Ok(TransitionOut {
+        if let Some(effect) = &self.effect {
+            effect(&event, ctx);
+        }
+        Ok(TransitionOut::Target {
             state: DynData::upcast(Box::new(self.to.clone())),
         })
     }
 
-    fn input_tid(&self) -> TypeId {
-        TypeId::of::<From>()
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<From>()
     }
 
-    fn output_tid(&self) -> TypeId {
-        TypeId::of::<To>()
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<To>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<E>()
     }
 }
 
-pub trait Transition<State: ?Sized = dyn Any> {
+/// Transition of the state machine. `Ctx` is the context of the state machine, that is passed to
+/// the transition together with the event.
+pub trait Transition<State: ?Sized = dyn Any, Ctx = ()> {
     fn transition(
         &self,
-        from: &mut Vertex<State>,
+        from: &mut Vertex<State, Ctx>,
         event: Event,
+        ctx: &mut Ctx,
     ) -> Result<TransitionOut<State>, TransitionError>;
-    fn input_tid(&self) -> TypeId;
+    /// Checks guards of the transition without performing it, so that the junction checks all
+    /// guards before any action runs, see `junction`.
+    fn check(
+        &self,
+        _from: &Vertex<State, Ctx>,
+        _event: &dyn Any,
+        _ctx: &mut Ctx,
+    ) -> Result<(), TransitionErrorKind> {
+        Ok(())
+    }
+    /// Performs the transition, whose guards are already satisfied, see `check`.
+    fn perform(
+        &self,
+        from: &mut Vertex<State, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<State>, TransitionError> {
+        self.transition(from, event, ctx)
+    }
+    /// Type of the input vertex data.
+    fn input_type(&self) -> TypeInfo;
+    /// Types of data of all vertexes this transition can end in.
+    ///
     /// This function is used only in the initialization moment to check that state machine contains
-    /// necessary output vertex.
-    fn output_tid(&self) -> TypeId;
+    /// necessary output vertexes.
+    fn output_types(&self) -> Vec<TypeInfo>;
+    /// Type of the event that triggers this transition.
+    fn event_type(&self) -> TypeInfo;
+    /// Returns `true` if the transition can be rejected by a guard.
+    fn is_guarded(&self) -> bool {
+        false
+    }
+    /// Names of guards checked by the transition, `None` for guards without names, see
+    /// `Guard::name`.
+    fn guards(&self) -> Vec<Option<&str>> {
+        vec![]
+    }
+    /// How the transition treats the source state.
+    fn kind(&self) -> TransitionKind {
+        TransitionKind::External
+    }
+    /// Describes the junction or choice pseudo-state the compound transition passes, so that
+    /// exporters can draw it, see `junction`.
+    fn compound(&self) -> Option<Compound<'_>> {
+        None
+    }
+    fn input_tid(&self) -> TypeId {
+        self.input_type().tid
+    }
+    /// Returns the first output vertex, see `output_types`.
+    fn output_tid(&self) -> TypeId {
+        self.output_types()[0].tid
+    }
+}
+
+impl<DynData: ?Sized, Ctx> TransitionInfo for dyn Transition<DynData, Ctx> {
+    fn input_type(&self) -> TypeInfo {
+        Transition::input_type(self)
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        Transition::output_types(self)
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        Transition::event_type(self)
+    }
+
+    fn compound(&self) -> Option<Compound<'_>> {
+        Transition::compound(self)
+    }
+}
+
+/// Transition with the type of the input vertex data known at compile time, so that it can be
+/// guarded by the data, see `GuardedTransition::guard_state`.
+pub trait TransitionInput {
+    /// Type of the input vertex data, the same as `Transition::input_type`.
+    type Input;
+}
+
+impl<From, E, To, Ctx> TransitionInput for Switch<From, E, To, Ctx> {
+    type Input = From;
+}
+
+/// Transition with the only output vertex, whose data type is known at compile time, so that it
+/// can lead to the junction or choice pseudo-state, see `junction`.
+pub trait TransitionOutput {
+    /// Type of the output vertex data, the only one of `Transition::output_types`.
+    type Output;
+}
+
+impl<From, E, To, Ctx> TransitionOutput for Switch<From, E, To, Ctx> {
+    type Output = To;
+}
+
+/// Transition with the type of the triggering event known at compile time, so that it can be
+/// registered in the state machine with the event enum, see `SmBuilder::with_events`.
+pub trait TransitionEvent {
+    /// Type of the event, the same as `Transition::event_type`.
+    type Event;
 }
 
-pub struct TransitionOut<State: ?Sized> {
-    pub state: Box<State>,
+impl<From, E, To, Ctx> TransitionEvent for Switch<From, E, To, Ctx> {
+    type Event = E;
+}
+
+/// How the transition treats the source state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// Source state is exited, then the target state is entered.
+    External,
+    /// Event is handled in the source state, which is neither exited nor entered, see `itrans`.
+    Internal,
+    /// Composite source state is not exited, only its nested state machine moves to the target
+    /// state, see `ltrans`.
+    Local,
+}
+
+/// Type of the vertex data or of the event, with its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TypeInfo {
+    pub tid: TypeId,
+    pub name: &'static str,
+}
+
+impl TypeInfo {
+    pub fn of<T: ?Sized + 'static>() -> Self {
+        TypeInfo {
+            tid: TypeId::of::<T>(),
+            name: core::any::type_name::<T>(),
+        }
+    }
+}
+
+/// Result of the transition that accepted the event.
+pub enum TransitionOut<State: ?Sized> {
+    /// The state machine moves to the state with this data. For local transitions it is the
+    /// state of the nested state machine, see `TransitionKind`.
+    Target { state: Box<State> },
+    /// The event is handled in the source state, which stays active, see `itrans`.
+    Stay,
 }
 
 pub struct TransitionError {
@@ -81,77 +250,709 @@ impl TransitionError {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum TransitionErrorKind {
+    /// The guard without a name rejected the event.
     GuardErr,
+    /// The same as `GuardErr`, with the name of the guard that rejected the event, see
+    /// `Guard::name`. The state machine reports it as `SmError::GuardRejected`.
+    NamedGuardErr(String),
     WrongEvent,
 }
 
 pub struct EmptyTransition;
 
-impl<DynData: ?Sized> Transition<DynData> for EmptyTransition {
+impl<DynData: ?Sized, Ctx> Transition<DynData, Ctx> for EmptyTransition {
     fn transition(
         &self,
-        _: &mut Vertex<DynData>,
+        _: &mut Vertex<DynData, Ctx>,
         _: Event,
+        _: &mut Ctx,
     ) -> Result<TransitionOut<DynData>, TransitionError> {
         unreachable!("It seems you forgot to initialize transition for something.")
     }
 
-    fn input_tid(&self) -> TypeId {
+    fn input_type(&self) -> TypeInfo {
         unreachable!("It seems you forgot to initialize transition for something.")
     }
 
-    fn output_tid(&self) -> TypeId {
+    fn output_types(&self) -> Vec<TypeInfo> {
+        unreachable!("It seems you forgot to initialize transition for something.")
+    }
+
+    fn event_type(&self) -> TypeInfo {
         unreachable!("It seems you forgot to initialize transition for something.")
     }
 }
 
-pub struct FuncTransition<F, Args>(F, PhantomData<Args>);
+pub struct FuncTransition<F, Args, Ctx = ()> {
+    f: F,
+    effect: Option<Effect<dyn Any, Ctx>>,
+    _args: PhantomData<Args>,
+}
 
-pub fn ftrans<F: Into<FuncTransition<F, Args>>, Args>(f: F) -> FuncTransition<F, Args> {
+/// Makes the transition from the function, that takes data of the source state and the event, and
+/// returns data of the target state. The function can take the context of the state machine as
+/// the third argument.
+pub fn ftrans<F, Args, Ctx>(f: F) -> FuncTransition<F, Args, Ctx>
+where
+    F: Into<FuncTransition<F, Args, Ctx>>,
+{
     f.into()
 }
 
-impl<F, Input, Output, Event> From<F> for FuncTransition<F, (Input, Event)>
+/// Arguments of the function of `FuncTransition`: data of the source state, the event and
+/// optionally the context.
+pub trait FuncArgs {
+    type Input;
+    type Event: Any;
+}
+
+impl<Input, FEvent: Any> FuncArgs for (Input, FEvent) {
+    type Input = Input;
+    type Event = FEvent;
+}
+
+impl<Input, FEvent: Any, Ctx> FuncArgs for (Input, FEvent, Ctx) {
+    type Input = Input;
+    type Event = FEvent;
+}
+
+impl<F, Args: FuncArgs, Ctx> TransitionInput for FuncTransition<F, Args, Ctx> {
+    type Input = Args::Input;
+}
+
+impl<F, Args: FuncArgs, Ctx> TransitionEvent for FuncTransition<F, Args, Ctx> {
+    type Event = Args::Event;
+}
+
+impl<F, Input, Output, Event, Ctx> TransitionOutput for FuncTransition<F, (Input, Event), Ctx>
+where
+    F: Fn(Input, Event) -> Output,
+{
+    type Output = Output;
+}
+
+impl<F, Input, Output, Event, Ctx> TransitionOutput for FuncTransition<F, (Input, Event, Ctx), Ctx>
+where
+    F: Fn(Input, Event, &mut Ctx) -> Output,
+{
+    type Output = Output;
+}
+
+impl<F, Input, Output, Event, Ctx> From<F> for FuncTransition<F, (Input, Event), Ctx>
 where
     F: Fn(Input, Event) -> Output,
     Input: Any,
     Output: Any,
 {
     fn from(f: F) -> Self {
-        Self(f, PhantomData)
+        FuncTransition {
+            f,
+            effect: None,
+            _args: PhantomData,
+        }
     }
 }
 
-impl<F, Input, Output, FEvent, DynData> Transition<DynData> for FuncTransition<F, (Input, FEvent)>
+impl<F, Input, Output, Event, Ctx> From<F> for FuncTransition<F, (Input, Event, Ctx), Ctx>
+where
+    F: Fn(Input, Event, &mut Ctx) -> Output,
+    Input: Any,
+    Output: Any,
+{
+    fn from(f: F) -> Self {
+        FuncTransition {
+            f,
+            effect: None,
+            _args: PhantomData,
+        }
+    }
+}
+
+impl<F, Args: FuncArgs, Ctx> FuncTransition<F, Args, Ctx> {
+    /// Sets the effect of the transition, that is called after the source state is exited and
+    /// before the function makes the target state.
+    pub fn effect(self, effect: impl Fn(&Args::Event) + 'static) -> Self {
+        self.effect_ctx(move |event, _| effect(event))
+    }
+
+    /// Sets the effect of the transition, that takes the context of the state machine too.
+    pub fn effect_ctx(self, effect: impl Fn(&Args::Event, &mut Ctx) + 'static) -> Self {
+        FuncTransition {
+            effect: Some(Box::new(move |event: &dyn Any, ctx: &mut Ctx| {
+                effect(
+                    event.downcast_ref().expect("Event type is checked before"),
+                    ctx,
+                )
+            })),
+            ..self
+        }
+    }
+}
+
+impl<F, Input, Output, FEvent, DynData, Ctx> Transition<DynData, Ctx>
+    for FuncTransition<F, (Input, FEvent), Ctx>
 where
     Input: 'static,
     Output: 'static,
     FEvent: Any + 'static,
     F: Fn(Input, FEvent) -> Output,
-    DynData: ?Sized + Cast<Input> + Cast<Output> + Cast<Sm<DynData>>,
+    DynData: ?Sized + Cast<Input> + Cast<Output>,
+    Ctx: 'static,
 {
     fn transition(
         &self,
-        from: &mut Vertex<DynData>,
+        from: &mut Vertex<DynData, Ctx>,
         event: Event,
+        ctx: &mut Ctx,
     ) -> Result<TransitionOut<DynData>, TransitionError> {
+        self.run(from, event, ctx, |input, fevent, _| (self.f)(input, fevent))
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Output>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+}
+
+impl<F, Input, Output, FEvent, DynData, Ctx> Transition<DynData, Ctx>
+    for FuncTransition<F, (Input, FEvent, Ctx), Ctx>
+where
+    Input: 'static,
+    Output: 'static,
+    FEvent: Any + 'static,
+    F: Fn(Input, FEvent, &mut Ctx) -> Output,
+    DynData: ?Sized + Cast<Input> + Cast<Output>,
+    Ctx: 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        self.run(from, event, ctx, &self.f)
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Output>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+}
+
+impl<F, Args, Ctx: 'static> FuncTransition<F, Args, Ctx> {
+    /// Exits the source state, calls the effect and makes the target state with `f`.
+    fn run<DynData, Input, FEvent, Output>(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+        f: impl Fn(Input, FEvent, &mut Ctx) -> Output,
+    ) -> Result<TransitionOut<DynData>, TransitionError>
+    where
+        Input: 'static,
+        Output: 'static,
+        FEvent: Any + 'static,
+        DynData: ?Sized + Cast<Input> + Cast<Output>,
+    {
         let fevent = event.downcast::<FEvent>().map_err(|e| TransitionError {
             event: e,
             kind: TransitionErrorKind::WrongEvent,
         })?;
-        from.exit();
+        from.exit(ctx);
         let input = from.get_data().downcast();
-        let out = (self.0)(*input, *fevent);
-        Ok(TransitionOut {
+        if let Some(effect) = &self.effect {
+            effect(fevent.as_ref(), ctx);
+        }
+        let out = f(*input, *fevent, ctx);
+        Ok(TransitionOut::Target {
             state: DynData::upcast(Box::new(out)),
         })
     }
+}
 
-    fn input_tid(&self) -> TypeId {
-        TypeId::of::<Input>()
+/// Internal transition, see `itrans`.
+pub struct InternalTransition<F, Args>(F, PhantomData<Args>);
+
+/// Makes the internal transition. The function handles the event with the data of the current
+/// state, which is neither exited nor entered, so entry and exit actions are not called and
+/// the nested state machine keeps its state. The function can take the context of the state
+/// machine as the third argument.
+pub fn itrans<F: Into<InternalTransition<F, Args>>, Args>(f: F) -> InternalTransition<F, Args> {
+    f.into()
+}
+
+impl<F, Input, FEvent> From<F> for InternalTransition<F, (Input, FEvent)>
+where
+    F: Fn(&mut Input, FEvent),
+{
+    fn from(f: F) -> Self {
+        InternalTransition(f, PhantomData)
     }
+}
 
-    fn output_tid(&self) -> TypeId {
-        TypeId::of::<Output>()
+impl<F, Input, FEvent, Ctx> From<F> for InternalTransition<F, (Input, FEvent, Ctx)>
+where
+    F: Fn(&mut Input, FEvent, &mut Ctx),
+{
+    fn from(f: F) -> Self {
+        InternalTransition(f, PhantomData)
+    }
+}
+
+impl<F, Input, FEvent, DynData, Ctx> Transition<DynData, Ctx>
+    for InternalTransition<F, (Input, FEvent)>
+where
+    Input: 'static,
+    FEvent: Any + 'static,
+    F: Fn(&mut Input, FEvent),
+    DynData: ?Sized + Cast<Input>,
+    Ctx: 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        _: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        let (input, fevent) = data_mut::<Input, FEvent, _, _>(from, event)?;
+        (self.0)(input, fevent);
+        Ok(TransitionOut::Stay)
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Input>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+
+    fn kind(&self) -> TransitionKind {
+        TransitionKind::Internal
+    }
+}
+
+impl<F, Input, FEvent, DynData, Ctx> Transition<DynData, Ctx>
+    for InternalTransition<F, (Input, FEvent, Ctx)>
+where
+    Input: 'static,
+    FEvent: Any + 'static,
+    F: Fn(&mut Input, FEvent, &mut Ctx),
+    DynData: ?Sized + Cast<Input>,
+    Ctx: 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        let (input, fevent) = data_mut::<Input, FEvent, _, _>(from, event)?;
+        (self.0)(input, fevent, ctx);
+        Ok(TransitionOut::Stay)
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Input>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+
+    fn kind(&self) -> TransitionKind {
+        TransitionKind::Internal
+    }
+}
+
+impl<F, Args: FuncArgs> TransitionInput for InternalTransition<F, Args> {
+    type Input = Args::Input;
+}
+
+impl<F, Args: FuncArgs> TransitionEvent for InternalTransition<F, Args> {
+    type Event = Args::Event;
+}
+
+/// Returns the data of the source state, that is changed in place by internal and local
+/// transitions, and the event. The event is rejected if it is not of type `FEvent`, or if the
+/// source state has no data of type `Input`.
+fn data_mut<Input, FEvent, DynData, Ctx>(
+    from: &mut Vertex<DynData, Ctx>,
+    event: Event,
+) -> Result<(&mut Input, FEvent), TransitionError>
+where
+    Input: 'static,
+    FEvent: Any + 'static,
+    DynData: ?Sized + 'static,
+    Ctx: 'static,
+{
+    let fevent = event.downcast::<FEvent>().map_err(|e| TransitionError {
+        event: e,
+        kind: TransitionErrorKind::WrongEvent,
+    })?;
+    match from
+        .get_data_as_any_mut()
+        .and_then(|data| data.downcast_mut())
+    {
+        Some(input) => Ok((input, *fevent)),
+        None => Err(TransitionError::new(
+            fevent,
+            TransitionErrorKind::WrongEvent,
+        )),
+    }
+}
+
+/// Local transition, see `ltrans`.
+pub struct LocalTransition<F, Args>(F, PhantomData<Args>);
+
+/// Makes the local transition from the composite state to the state of its nested state
+/// machine. The composite state is not exited, only the current nested state is exited before
+/// the target state is entered. The function can take the context of the state machine as the
+/// third argument.
+pub fn ltrans<F: Into<LocalTransition<F, Args>>, Args>(f: F) -> LocalTransition<F, Args> {
+    f.into()
+}
+
+impl<F, Args: FuncArgs> TransitionInput for LocalTransition<F, Args> {
+    type Input = Args::Input;
+}
+
+impl<F, Args: FuncArgs> TransitionEvent for LocalTransition<F, Args> {
+    type Event = Args::Event;
+}
+
+impl<F, Input, FEvent, Output> From<F> for LocalTransition<F, (Input, FEvent)>
+where
+    F: Fn(&mut Input, FEvent) -> Output,
+{
+    fn from(f: F) -> Self {
+        LocalTransition(f, PhantomData)
+    }
+}
+
+impl<F, Input, FEvent, Output, Ctx> From<F> for LocalTransition<F, (Input, FEvent, Ctx)>
+where
+    F: Fn(&mut Input, FEvent, &mut Ctx) -> Output,
+{
+    fn from(f: F) -> Self {
+        LocalTransition(f, PhantomData)
+    }
+}
+
+impl<F, Input, FEvent, Output, DynData, Ctx> Transition<DynData, Ctx>
+    for LocalTransition<F, (Input, FEvent)>
+where
+    Input: 'static,
+    Output: 'static,
+    FEvent: Any + 'static,
+    F: Fn(&mut Input, FEvent) -> Output,
+    DynData: ?Sized + Cast<Input> + Cast<Output>,
+    Ctx: 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        _: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        let (input, fevent) = data_mut::<Input, FEvent, _, _>(from, event)?;
+        let out = (self.0)(input, fevent);
+        Ok(TransitionOut::Target {
+            state: DynData::upcast(Box::new(out)),
+        })
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Output>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+
+    fn kind(&self) -> TransitionKind {
+        TransitionKind::Local
+    }
+}
+
+impl<F, Input, FEvent, Output, DynData, Ctx> Transition<DynData, Ctx>
+    for LocalTransition<F, (Input, FEvent, Ctx)>
+where
+    Input: 'static,
+    Output: 'static,
+    FEvent: Any + 'static,
+    F: Fn(&mut Input, FEvent, &mut Ctx) -> Output,
+    DynData: ?Sized + Cast<Input> + Cast<Output>,
+    Ctx: 'static,
+{
+    fn transition(
+        &self,
+        from: &mut Vertex<DynData, Ctx>,
+        event: Event,
+        ctx: &mut Ctx,
+    ) -> Result<TransitionOut<DynData>, TransitionError> {
+        let (input, fevent) = data_mut::<Input, FEvent, _, _>(from, event)?;
+        let out = (self.0)(input, fevent, ctx);
+        Ok(TransitionOut::Target {
+            state: DynData::upcast(Box::new(out)),
+        })
+    }
+
+    fn input_type(&self) -> TypeInfo {
+        TypeInfo::of::<Input>()
+    }
+
+    fn output_types(&self) -> Vec<TypeInfo> {
+        vec![TypeInfo::of::<Output>()]
+    }
+
+    fn event_type(&self) -> TypeInfo {
+        TypeInfo::of::<FEvent>()
+    }
+
+    fn kind(&self) -> TransitionKind {
+        TransitionKind::Local
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{InitialPseudoState, SimpleVertex};
+    use crate::transition::ftrans;
+    use crate::{BuildError, EnterSmEvent, SmBuilder};
+    use std::any::Any;
+
+    #[test]
+    fn test_internal_and_local_transitions() {
+        use crate::transition::{itrans, ltrans};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq)]
+        struct Playing {
+            volume: u8,
+        }
+        #[derive(Debug, PartialEq)]
+        struct Track1;
+        #[derive(Debug, PartialEq)]
+        struct Track2;
+        #[derive(Debug, PartialEq)]
+        struct Volume(u8);
+        #[derive(Debug, PartialEq)]
+        struct Next;
+        #[derive(Debug, PartialEq)]
+        struct Restart;
+
+        type Log = Rc<RefCell<Vec<&'static str>>>;
+        fn hook<T>(log: &Log, entry: &'static str) -> impl Fn(&T) + Clone {
+            let log = log.clone();
+            move |_| log.borrow_mut().push(entry)
+        }
+
+        let log = Log::default();
+        let (enter_track1, exit_track2) = (hook(&log, "enter Track1"), hook(&log, "exit Track2"));
+        let mut sm = SmBuilder::<dyn Any>::new()
+            .register_vertex(
+                SimpleVertex::<Playing>::new()
+                    .with_entry(hook(&log, "enter Playing"))
+                    .with_exit(hook(&log, "exit Playing"))
+                    .to_submachine_vertex(move || {
+                        let (enter_track1, exit_track2) =
+                            (enter_track1.clone(), exit_track2.clone());
+                        SmBuilder::new()
+                            .register_vertex(
+                                SimpleVertex::<Track1>::new()
+                                    .with_entry(enter_track1)
+                                    .to_vertex(),
+                            )
+                            .register_vertex(
+                                SimpleVertex::<Track2>::new()
+                                    .with_exit(exit_track2)
+                                    .to_vertex(),
+                            )
+                            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Track1))
+                            .transition(ftrans(|_: Track1, _: Next| Track2))
+                            .transition(ftrans(|_: Track2, _: Next| Track1))
+                    }),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Playing {
+                volume: 5,
+            }))
+            .transition(itrans(|p: &mut Playing, v: Volume| p.volume = v.0))
+            .transition(ltrans(|_: &mut Playing, _: Restart| Track1))
+            .build()
+            .unwrap();
+
+        assert_eq!(sm.process(Next), Ok(()));
+        assert_eq!(sm.process(Volume(7)), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Playing { volume: 7 }));
+        assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Track2));
+
+        assert_eq!(sm.process(Restart), Ok(()));
+        assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Track1));
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "enter Playing",
+                "enter Track1",
+                "exit Track2",
+                "enter Track1"
+            ]
+        );
+
+        let errors = SmBuilder::<dyn Any>::new()
+            .register_vertex(SimpleVertex::<Track1>::new().to_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Track1))
+            .transition(ltrans(|_: &mut Track1, _: Restart| Track2))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![BuildError::MissingOutputVertex(core::any::type_name::<
+                Track2,
+            >())]
+        );
+    }
+
+    #[test]
+    fn test_internal_and_local_transitions_with_ctx() {
+        use crate::transition::{itrans, ltrans};
+
+        #[derive(Debug, PartialEq)]
+        struct Playing {
+            volume: u8,
+        }
+        #[derive(Debug, PartialEq)]
+        struct Track1;
+        #[derive(Debug, PartialEq)]
+        struct Track2;
+        #[derive(Debug, PartialEq)]
+        struct Volume(u8);
+        #[derive(Debug, PartialEq)]
+        struct Next;
+        #[derive(Debug, PartialEq)]
+        struct Restart;
+
+        let mut sm = SmBuilder::<dyn Any, u32>::new()
+            .register_vertex(
+                SimpleVertex::<Playing, u32>::new().to_submachine_vertex(|| {
+                    SmBuilder::new()
+                        .register_vertex(SimpleVertex::<Track1, u32>::new().to_vertex())
+                        .register_vertex(SimpleVertex::<Track2, u32>::new().to_vertex())
+                        .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Track1))
+                        .transition(ftrans(|_: Track1, _: Next| Track2))
+                        .transition(ftrans(|_: Track2, _: Next| Track1))
+                }),
+            )
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Playing {
+                volume: 5,
+            }))
+            .transition(itrans(|p: &mut Playing, v: Volume, changes: &mut u32| {
+                p.volume = v.0;
+                *changes += 1;
+            }))
+            .transition(ltrans(|_: &mut Playing, _: Restart, changes: &mut u32| {
+                *changes += 1;
+                Track1
+            }))
+            .build()
+            .unwrap();
+
+        assert_eq!(sm.process(Next), Ok(()));
+        assert_eq!(sm.process(Volume(7)), Ok(()));
+        assert_eq!(sm.current_state_concrete(), Some(&Playing { volume: 7 }));
+        assert_eq!(sm.process(Restart), Ok(()));
+        assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Track1));
+        assert_eq!(sm.ctx(), Some(&2));
+    }
+
+    #[test]
+    fn test_transition_effects() {
+        use crate::transition::Switch;
+
+        #[derive(Debug, PartialEq)]
+        struct Red;
+        #[derive(Debug, PartialEq, Clone)]
+        struct Green;
+        #[derive(Debug, PartialEq)]
+        struct Yellow;
+        #[derive(Debug, PartialEq)]
+        struct Go(u32);
+        #[derive(Debug, PartialEq)]
+        struct Slow;
+        #[derive(Debug, PartialEq)]
+        struct Stop;
+
+        let mut sm = SmBuilder::<dyn Any, Vec<String>>::new()
+            .register_vertex(
+                SimpleVertex::<Red, Vec<String>>::new()
+                    .with_exit_ctx(|_, log| log.push("exit Red".to_string()))
+                    .to_vertex(),
+            )
+            .register_vertex(
+                SimpleVertex::<Green, Vec<String>>::new()
+                    .with_entry_ctx(|_, log| log.push("enter Green".to_string()))
+                    .to_vertex(),
+            )
+            .register_vertex(SimpleVertex::<Yellow, _>::new().to_vertex())
+            .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Red))
+            .transition(
+                Switch::<Red, Go, _, _>::new(Green)
+                    .effect_ctx(|go, log: &mut Vec<String>| log.push(format!("go {}", go.0))),
+            )
+            .transition(
+                ftrans(|_: Green, _: Slow| Yellow)
+                    .effect_ctx(|_, log: &mut Vec<String>| log.push("slow".to_string())),
+            )
+            .transition(
+                ftrans(|_: Yellow, _: Stop, log: &mut Vec<String>| {
+                    log.push("make Red".to_string());
+                    Red
+                })
+                .effect_ctx(|_, log| log.push("stop".to_string())),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(sm.process(Go(1)), Ok(()));
+        assert_eq!(sm.process(Slow), Ok(()));
+        assert_eq!(sm.process(Stop), Ok(()));
+        assert_eq!(
+            *sm.ctx().unwrap(),
+            vec![
+                "exit Red",
+                "go 1",
+                "enter Green",
+                "slow",
+                "stop",
+                "make Red"
+            ]
+        );
     }
 }
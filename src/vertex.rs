@@ -1,7 +1,13 @@
-use crate::state::{Cast, SimpleVertex};
-use crate::Sm;
-use std::any::{Any, TypeId};
-use std::fmt::{Debug, Formatter};
+use crate::sm::VertexInfo;
+use crate::state::{
+    Cast, DeepHistoryPseudoState, OrthogonalVertex, Region, ShallowHistoryPseudoState,
+    SubMachineVertex, TerminatePseudoState,
+};
+use crate::transition::TypeInfo;
+use crate::BuildError;
+use alloc::boxed::Box;
+use core::any::{Any, TypeId};
+use core::fmt::{Debug, Formatter};
 
 /// Possible vertexes.
 ///
@@ -9,105 +15,284 @@ use std::fmt::{Debug, Formatter};
 /// 1. There are can be multiple instances of the state.
 /// 2. There are can be multiple transitions *to* this state.
 /// 3. There are can be multiple transitions *from* this state.
-pub enum Vertex<DynData: ?Sized> {
-    State(Box<dyn StateTrait<DynData>>),
-    SubMachineState(SimpleVertex<Sm<DynData>>),
-    PseudoState(PseudoState<DynData>),
+///
+/// `Ctx` is the context of the state machine, that is passed to entry and exit actions.
+pub enum Vertex<DynData: ?Sized, Ctx = ()> {
+    State(Box<dyn StateTrait<DynData, Ctx>>),
+    SubMachineState(SubMachineVertex<DynData, Ctx>),
+    /// Composite state with several concurrent regions.
+    OrthogonalState(OrthogonalVertex<DynData, Ctx>),
+    PseudoState(PseudoState<DynData, Ctx>),
+    /// State that means that the enclosing state machine is finished. When the nested state
+    /// machine reaches the final state, the outer state machine receives `CompletionEvent`.
+    ///
+    /// ABI:
+    /// 1. Only 0 transitions from this state is allowed.
+    FinalState(Box<dyn StateTrait<DynData, Ctx>>),
 }
 
-impl<DynData> Debug for Vertex<DynData> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl<DynData: ?Sized, Ctx> Vertex<DynData, Ctx> {
+    /// Creates a terminate pseudo-state. See [`PseudoStateKind::Terminate`].
+    pub fn terminate() -> Self
+    where
+        DynData: Cast<TerminatePseudoState>,
+    {
+        Vertex::PseudoState(PseudoState::new(
+            Some(Box::new(TerminatePseudoState)),
+            PseudoStateKind::Terminate,
+        ))
+    }
+
+    /// Creates a shallow history pseudo-state. See [`PseudoStateKind::ShallowHistory`].
+    pub fn shallow_history() -> Self
+    where
+        DynData: Cast<ShallowHistoryPseudoState>,
+    {
+        Vertex::PseudoState(PseudoState::new(
+            Some(Box::new(ShallowHistoryPseudoState)),
+            PseudoStateKind::ShallowHistory,
+        ))
+    }
+
+    /// Creates a deep history pseudo-state. See [`PseudoStateKind::DeepHistory`].
+    pub fn deep_history() -> Self
+    where
+        DynData: Cast<DeepHistoryPseudoState>,
+    {
+        Vertex::PseudoState(PseudoState::new(
+            Some(Box::new(DeepHistoryPseudoState)),
+            PseudoStateKind::DeepHistory,
+        ))
+    }
+}
+
+impl<DynData: ?Sized, Ctx> Vertex<DynData, Ctx> {
+    /// Returns regions of the composite state, or nothing for other vertexes.
+    pub(crate) fn regions(&self) -> &[Region<DynData, Ctx>] {
+        match self {
+            Vertex::SubMachineState(sub) => core::slice::from_ref(&sub.region),
+            Vertex::OrthogonalState(o) => &o.regions,
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn regions_mut(&mut self) -> &mut [Region<DynData, Ctx>] {
+        match self {
+            Vertex::SubMachineState(sub) => core::slice::from_mut(&mut sub.region),
+            Vertex::OrthogonalState(o) => &mut o.regions,
+            _ => &mut [],
+        }
+    }
+}
+
+impl<DynData: ?Sized, Ctx> Debug for Vertex<DynData, Ctx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Vertex").finish()
     }
 }
 
-impl<DynData: ?Sized> StateTrait<DynData> for Vertex<DynData>
-where
-    DynData: Cast<Sm<DynData>>,
-{
-    fn entry(&self) {
+impl<DynData: ?Sized + 'static, Ctx: 'static> VertexInfo for Vertex<DynData, Ctx> {
+    fn data_type(&self) -> TypeInfo {
+        TypeInfo {
+            tid: self.data_tid(),
+            name: self.data_type_name(),
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        matches!(self, Vertex::FinalState(_))
+    }
+
+    fn is_terminate(&self) -> bool {
+        matches!(
+            self,
+            Vertex::PseudoState(PseudoState {
+                kind: PseudoStateKind::Terminate,
+                ..
+            })
+        )
+    }
+
+    fn is_history(&self) -> bool {
+        matches!(
+            self,
+            Vertex::PseudoState(PseudoState {
+                kind: PseudoStateKind::ShallowHistory | PseudoStateKind::DeepHistory,
+                ..
+            })
+        )
+    }
+
+    /// Returns `true` if the vertex may have no outgoing transitions: final states, terminate
+    /// and history pseudo-states, and composite states, that run their nested state machines.
+    fn may_stay(&self) -> bool {
         match self {
-            Vertex::State(s) => s.entry(),
-            Vertex::SubMachineState(sm) => sm.entry(),
-            Vertex::PseudoState(ps) => ps.entry(),
+            Vertex::FinalState(_) | Vertex::SubMachineState(_) | Vertex::OrthogonalState(_) => true,
+            Vertex::PseudoState(ps) => matches!(
+                ps.kind,
+                PseudoStateKind::Terminate
+                    | PseudoStateKind::ShallowHistory
+                    | PseudoStateKind::DeepHistory
+            ),
+            Vertex::State(_) => false,
         }
     }
-    fn exit(&self) {
+}
+
+impl<DynData: ?Sized + 'static, Ctx: 'static> StateTrait<DynData, Ctx> for Vertex<DynData, Ctx> {
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
         match self {
-            Vertex::State(s) => s.exit(),
-            Vertex::SubMachineState(sm) => sm.exit(),
-            Vertex::PseudoState(ps) => ps.exit(),
+            Vertex::State(s) => s.entry(ctx),
+            Vertex::SubMachineState(sm) => sm.entry(ctx),
+            Vertex::OrthogonalState(o) => o.entry(ctx),
+            Vertex::PseudoState(ps) => ps.entry(ctx),
+            Vertex::FinalState(s) => s.entry(ctx),
+        }
+    }
+    fn exit(&mut self, ctx: &mut Ctx) {
+        match self {
+            Vertex::State(s) => s.exit(ctx),
+            Vertex::SubMachineState(sm) => sm.exit(ctx),
+            Vertex::OrthogonalState(o) => o.exit(ctx),
+            Vertex::PseudoState(ps) => ps.exit(ctx),
+            Vertex::FinalState(s) => s.exit(ctx),
         }
     }
 
     fn get_data(&mut self) -> Box<DynData> {
         match self {
             Vertex::State(s) => s.get_data(),
-            Vertex::SubMachineState(_) => unreachable!(),
+            Vertex::SubMachineState(sm) => sm.get_data(),
+            Vertex::OrthogonalState(o) => o.get_data(),
             Vertex::PseudoState(ps) => ps.get_data(),
+            Vertex::FinalState(s) => s.get_data(),
         }
     }
 
     fn get_data_as_ref(&self) -> &DynData {
         match self {
             Vertex::State(s) => s.get_data_as_ref(),
-            Vertex::SubMachineState(_) => unreachable!(),
+            Vertex::SubMachineState(sm) => sm.get_data_as_ref(),
+            Vertex::OrthogonalState(o) => o.get_data_as_ref(),
             Vertex::PseudoState(ps) => ps.get_data_as_ref(),
+            Vertex::FinalState(s) => s.get_data_as_ref(),
+        }
+    }
+
+    fn get_data_as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        match self {
+            Vertex::State(s) => s.get_data_as_any_mut(),
+            Vertex::SubMachineState(sm) => sm.get_data_as_any_mut(),
+            Vertex::OrthogonalState(o) => o.get_data_as_any_mut(),
+            Vertex::PseudoState(ps) => ps.get_data_as_any_mut(),
+            Vertex::FinalState(s) => s.get_data_as_any_mut(),
         }
     }
 
     fn set_data(&mut self, data: Box<DynData>) {
         match self {
             Vertex::State(s) => s.set_data(data),
-            Vertex::SubMachineState(_) => unreachable!(),
+            Vertex::SubMachineState(sm) => sm.set_data(data),
+            Vertex::OrthogonalState(o) => o.set_data(data),
             Vertex::PseudoState(ps) => ps.set_data(data),
+            Vertex::FinalState(s) => s.set_data(data),
         }
     }
 
     fn data_tid(&self) -> TypeId {
         match self {
             Vertex::State(s) => s.data_tid(),
-            Vertex::SubMachineState(sm) => unreachable!(),
+            Vertex::SubMachineState(sm) => sm.data_tid(),
+            Vertex::OrthogonalState(o) => o.data_tid(),
             Vertex::PseudoState(ps) => ps.data_tid(),
+            Vertex::FinalState(s) => s.data_tid(),
+        }
+    }
+
+    fn data_type_name(&self) -> &'static str {
+        match self {
+            Vertex::State(s) => s.data_type_name(),
+            Vertex::SubMachineState(sm) => sm.data_type_name(),
+            Vertex::OrthogonalState(o) => o.data_type_name(),
+            Vertex::PseudoState(ps) => ps.data_type_name(),
+            Vertex::FinalState(s) => s.data_type_name(),
+        }
+    }
+    fn has_entry(&self) -> bool {
+        match self {
+            Vertex::State(s) => s.has_entry(),
+            Vertex::SubMachineState(sm) => sm.has_entry(),
+            Vertex::OrthogonalState(o) => o.has_entry(),
+            Vertex::PseudoState(ps) => ps.has_entry(),
+            Vertex::FinalState(s) => s.has_entry(),
+        }
+    }
+    fn has_exit(&self) -> bool {
+        match self {
+            Vertex::State(s) => s.has_exit(),
+            Vertex::SubMachineState(sm) => sm.has_exit(),
+            Vertex::OrthogonalState(o) => o.has_exit(),
+            Vertex::PseudoState(ps) => ps.has_exit(),
+            Vertex::FinalState(s) => s.has_exit(),
+        }
+    }
+    fn deferred(&self) -> &[TypeInfo] {
+        match self {
+            Vertex::State(s) => s.deferred(),
+            Vertex::SubMachineState(sm) => sm.deferred(),
+            Vertex::OrthogonalState(o) => o.deferred(),
+            Vertex::PseudoState(ps) => ps.deferred(),
+            Vertex::FinalState(s) => s.deferred(),
         }
     }
 }
 
-pub struct PseudoState<DynData: ?Sized> {
+pub struct PseudoState<DynData: ?Sized, Ctx = ()> {
     pub(crate) data: Option<Box<DynData>>,
     pub(crate) data_tid: TypeId,
-    pub(crate) kind: PseudoStateKind,
+    pub(crate) data_type_name: &'static str,
+    pub(crate) kind: PseudoStateKind<Ctx>,
 }
 
-impl<DynData: ?Sized> PseudoState<DynData> {
-    pub fn new<T: 'static>(data: Option<Box<T>>, kind: PseudoStateKind) -> Self
+impl<DynData: ?Sized, Ctx> PseudoState<DynData, Ctx> {
+    pub fn new<T: 'static>(data: Option<Box<T>>, kind: PseudoStateKind<Ctx>) -> Self
     where
         DynData: Cast<T>,
     {
         PseudoState {
             data: data.map(|x| DynData::upcast(x)),
             data_tid: TypeId::of::<T>(),
+            data_type_name: core::any::type_name::<T>(),
             kind,
         }
     }
 }
 
-impl<DynData: ?Sized> StateTrait<DynData> for PseudoState<DynData> {
-    fn entry(&self) {
+impl<DynData: ?Sized, Ctx> StateTrait<DynData, Ctx> for PseudoState<DynData, Ctx> {
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), BuildError> {
         match &self.kind {
             PseudoStateKind::Initial => {}
             PseudoStateKind::Terminate => {}
-            PseudoStateKind::Entry(action) => action.perform_action(),
+            PseudoStateKind::ShallowHistory => {}
+            PseudoStateKind::DeepHistory => {}
+            PseudoStateKind::Junction => {}
+            PseudoStateKind::Choice => {}
+            PseudoStateKind::Entry(action) => action.perform_action(ctx),
             PseudoStateKind::Exit(_) => {}
         }
+        Ok(())
     }
 
-    fn exit(&self) {
+    fn exit(&mut self, ctx: &mut Ctx) {
         match &self.kind {
             PseudoStateKind::Initial => {}
             PseudoStateKind::Terminate => {}
+            PseudoStateKind::ShallowHistory => {}
+            PseudoStateKind::DeepHistory => {}
+            PseudoStateKind::Junction => {}
+            PseudoStateKind::Choice => {}
             PseudoStateKind::Entry(_) => {}
-            PseudoStateKind::Exit(action) => action.perform_action(),
+            PseudoStateKind::Exit(action) => action.perform_action(ctx),
         }
     }
 
@@ -123,15 +308,28 @@ impl<DynData: ?Sized> StateTrait<DynData> for PseudoState<DynData> {
             .expect("This method must be called only once.")
     }
 
+    fn get_data_as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
     fn set_data(&mut self, data: Box<DynData>) {
         self.data = Some(data)
     }
     fn data_tid(&self) -> TypeId {
         self.data_tid
     }
+    fn data_type_name(&self) -> &'static str {
+        self.data_type_name
+    }
+    fn has_entry(&self) -> bool {
+        matches!(self.kind, PseudoStateKind::Entry(_))
+    }
+    fn has_exit(&self) -> bool {
+        matches!(self.kind, PseudoStateKind::Exit(_))
+    }
 }
 
-pub enum PseudoStateKind {
+pub enum PseudoStateKind<Ctx = ()> {
     /// Points to the initial state of the State machine.
     ///
     /// ABI:
@@ -140,30 +338,101 @@ pub enum PseudoStateKind {
     Initial,
     /// State that means that state machine is exited and cannot be used.
     ///
+    /// When the terminate state is entered in the nested state machine, the outer state machine
+    /// exits the composite state and is terminated too.
+    ///
     /// ABI:
     /// 1. Only 0 transitions from this state is allowed.
     Terminate,
+    /// Remembers the last active state of the state machine it belongs to. When the composite
+    /// state that contains the state machine is re-entered, the last active state is entered
+    /// again instead of the initial one. Nested composite states are entered as usual.
+    ///
+    /// Transition *from* this state on `EnterSmEvent` is used when there is no history yet,
+    /// otherwise the `Initial` state is used.
+    ///
+    /// ABI, checked by `SmBuilder::build`:
+    /// 1. There are can be only one history state in the state machine.
+    /// 2. There are can be only one transition *from* this state.
+    ShallowHistory,
+    /// The same as `ShallowHistory`, but restores the whole configuration of the nested
+    /// composite states too.
+    ///
+    /// ABI, checked by `SmBuilder::build`:
+    /// 1. There are can be only one history state in the state machine.
+    /// 2. There are can be only one transition *from* this state.
+    DeepHistory,
+    /// Static conditional branch. Guards of all outgoing segments are evaluated against the
+    /// event before any action of the compound transition is performed. See
+    /// [`crate::junction::Junction`].
+    ///
+    /// ABI:
+    /// 1. State machine never stays in this state.
+    /// 2. There are must be an `else` transition *from* this state.
+    Junction,
+    /// Dynamic conditional branch. Guards of outgoing segments are evaluated against the data
+    /// produced by the incoming segment, i.e. after its action is performed. See
+    /// [`crate::junction::Choice`].
+    ///
+    /// ABI:
+    /// 1. State machine never stays in this state.
+    /// 2. There are must be an `else` transition *from* this state.
+    Choice,
     /// Contains action that will be called when transition enters this pseudo-state.
     ///
     /// ABI:
     /// 1. There are can be only one transition *from* this state.
-    Entry(Box<dyn ActionPoint>),
+    Entry(Box<dyn ActionPoint<Ctx>>),
     /// Contains action that will be called when transition exited this pseudo-state.
     ///
     /// ABI:
     /// 1. There are can be only one transition *from* this state.
-    Exit(Box<dyn ActionPoint>),
+    Exit(Box<dyn ActionPoint<Ctx>>),
 }
 
-pub trait StateTrait<DynData: ?Sized> {
-    fn entry(&self);
-    fn exit(&self);
+/// State of the state machine. `Ctx` is the context of the state machine, that is passed to
+/// entry and exit actions.
+pub trait StateTrait<DynData: ?Sized, Ctx = ()> {
+    /// Enters the state. Fails if the nested state machine of the composite state cannot be
+    /// started, see `BuildError::InvalidSubmachine`.
+    fn entry(&mut self, ctx: &mut Ctx) -> Result<(), BuildError>;
+    fn exit(&mut self, ctx: &mut Ctx);
     fn get_data(&mut self) -> Box<DynData>;
     fn get_data_as_ref(&self) -> &DynData;
+    /// Returns the data of the state for internal and local transitions, which change it in
+    /// place. Pseudo-states have no such data and return `None`.
+    fn get_data_as_any_mut(&mut self) -> Option<&mut dyn Any>;
     fn set_data(&mut self, data: Box<DynData>);
     fn data_tid(&self) -> TypeId;
+    fn data_type_name(&self) -> &'static str;
+    /// Returns `true` if the state performs an action on entry.
+    fn has_entry(&self) -> bool {
+        false
+    }
+    /// Returns `true` if the state performs an action on exit.
+    fn has_exit(&self) -> bool {
+        false
+    }
+    /// Types of events deferred by the state.
+    fn deferred(&self) -> &[TypeInfo] {
+        &[]
+    }
+    /// Returns `true` if the state defers events of the type `event`.
+    fn defers(&self, event: TypeId) -> bool {
+        self.deferred().iter().any(|e| e.tid == event)
+    }
 }
 
-pub trait ActionPoint {
-    fn perform_action(&self);
+/// Action of the entry or exit pseudo-state. It receives the context of the state machine.
+pub trait ActionPoint<Ctx = ()> {
+    fn perform_action(&self, ctx: &mut Ctx);
+}
+
+impl<F, Ctx> ActionPoint<Ctx> for F
+where
+    F: Fn(&mut Ctx),
+{
+    fn perform_action(&self, ctx: &mut Ctx) {
+        self(ctx)
+    }
 }
@@ -0,0 +1,162 @@
+use umlsm::state_machine;
+use umlsm::SmError;
+
+#[test]
+fn test_state_machine_macro() {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static LOG: RefCell<Vec<&'static str>> = const { RefCell::new(vec![]) };
+    }
+    fn log(entry: &'static str) {
+        LOG.with(|l| l.borrow_mut().push(entry));
+    }
+
+    state_machine! {
+        machine Player;
+
+        #[derive(Debug, PartialEq)]
+        events { Play, Pause, Next, Stop }
+
+        #[derive(Debug, PartialEq)]
+        states {
+            Idle,
+            Playing(entry = |_| log("enter Playing")) {
+                #[derive(Debug, PartialEq)]
+                states { Track1, Track2, final LastTrack }
+                transitions {
+                    [*] = Track1;
+                    Track1 + Next = Track2;
+                    Track2 + Next = LastTrack;
+                }
+            },
+            Paused,
+            final Stopped,
+        }
+
+        transitions {
+            [*] = Idle;
+            Idle + Play [|p: &Play| *p == Play] = Playing / |_, _| log("play");
+            Playing + Pause = Paused;
+            Paused + Play = Playing;
+            Playing = Idle;
+            Idle + Stop = Stopped;
+        }
+    }
+
+    let mut sm = Player::build().unwrap();
+    assert_eq!(sm.current_state_concrete(), Some(&Idle));
+    assert_eq!(sm.process(Play), Ok(()));
+    assert_eq!(sm.process(Next), Ok(()));
+    assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Track2));
+    assert_eq!(sm.process(Pause), Ok(()));
+    assert_eq!(sm.process(Play), Ok(()));
+    assert_eq!(sm.active_states()[0].downcast_ref(), Some(&Track1));
+    assert_eq!(sm.process(Next), Ok(()));
+    assert_eq!(sm.process(Next), Ok(()));
+    assert_eq!(sm.current_state_concrete(), Some(&Idle));
+    assert_eq!(sm.process(Stop), Ok(()));
+    assert!(sm.is_finished());
+    LOG.with(|l| assert_eq!(*l.borrow(), vec!["play", "enter Playing", "enter Playing"]));
+}
+
+#[test]
+fn test_static_state_machine() {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static LOG: RefCell<Vec<&'static str>> = const { RefCell::new(vec![]) };
+    }
+    fn log(entry: &'static str) {
+        LOG.with(|l| l.borrow_mut().push(entry));
+    }
+
+    state_machine! {
+        machine Lamp, static StaticLamp;
+
+        #[derive(Debug, PartialEq)]
+        events { Toggle, Blink, Tick }
+
+        #[derive(Debug, PartialEq)]
+        states {
+            Off(entry = |_| log("enter Off"), exit = |_| log("exit Off")),
+            On(entry = |_| log("enter On"), exit = |_| log("exit On")) {
+                #[derive(Debug, PartialEq)]
+                states {
+                    Steady(exit = |_| log("exit Steady")),
+                    Blinking(entry = |_| log("enter Blinking")),
+                    final Burnt,
+                }
+                transitions {
+                    [*] = Steady;
+                    Steady + Blink = Blinking;
+                    Blinking + Tick [|_| true] = Burnt / |_, _| log("burn");
+                }
+            },
+        }
+
+        transitions {
+            [*] = Off;
+            Off + Toggle = On / |_, _| log("switch on");
+            On + Toggle = Off;
+            On = Off;
+        }
+    }
+
+    let take = || LOG.with(|l| l.take());
+
+    let mut sm = Lamp::build().unwrap();
+    assert_eq!(sm.process(Toggle), Ok(()));
+    assert_eq!(sm.process(Blink), Ok(()));
+    assert_eq!(sm.process(Toggle), Ok(()));
+    assert_eq!(sm.process(Toggle), Ok(()));
+    assert!(sm.process(Tick).is_err());
+    assert_eq!(sm.process(Blink), Ok(()));
+    assert_eq!(sm.process(Tick), Ok(()));
+    assert_eq!(sm.current_state_concrete(), Some(&Off));
+    let dynamic = take();
+
+    let mut sm = StaticLamp::new();
+    assert!(matches!(sm.state(), StaticLampState::Off(Off)));
+    for event in [Toggle.into(), Blink.into(), Toggle.into(), Toggle.into()] {
+        assert!(sm.process(event).is_ok());
+    }
+    match sm.state() {
+        StaticLampState::On(On, nested) => {
+            assert!(matches!(nested.state(), StaticLampOnState::Steady(Steady)))
+        }
+        _ => panic!("The lamp must be on"),
+    }
+    assert!(matches!(
+        sm.process(Tick.into()),
+        Err(SmError::NoTransitionSatisfyingEvent(StaticLampEvent::Tick(
+            Tick
+        )))
+    ));
+    assert!(sm.process(Blink.into()).is_ok());
+    assert!(sm.process(Tick.into()).is_ok());
+    assert!(matches!(sm.state(), StaticLampState::Off(Off)));
+    assert!(!sm.is_finished());
+    assert_eq!(take(), dynamic);
+    assert_eq!(
+        dynamic,
+        vec![
+            "enter Off",
+            "exit Off",
+            "switch on",
+            "enter On",
+            "exit Steady",
+            "enter Blinking",
+            "exit On",
+            "enter Off",
+            "exit Off",
+            "switch on",
+            "enter On",
+            "exit Steady",
+            "enter Blinking",
+            "burn",
+            "exit On",
+            "enter Off",
+        ]
+    );
+}
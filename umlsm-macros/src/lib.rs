@@ -0,0 +1,168 @@
+//! Procedural macros of the `umlsm` crate. Use them through the re-exports in `umlsm`.
+
+mod sm_event;
+mod state_machine;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+/// Declares states, events and transitions of the state machine in one block.
+///
+/// The block starts with the header `<vis> machine Name: DynData;`, where `DynData` is the type
+/// of the state data in the state machine, `dyn Any` by default. Then follow the sections:
+/// - `events { A, B }` declares unit structs for events;
+/// - `states { A, B }` declares unit structs for states. A state can be prefixed with `final`,
+///   can have entry and exit actions `A(entry = f, exit = g)`, and can contain the nested state
+///   machine with its own `states` and `transitions` sections in braces;
+/// - `transitions { ... }` describes transitions of the state machine as
+///   `Source + Event [guard] = Target / action;`. Use `[*]` as the source for the transition from
+///   the initial pseudo-state. A transition without an event is triggered by `CompletionEvent`.
+///   The guard is `Fn(&Event) -> bool`, the action is `Fn(&Source, &Event)`.
+///
+/// Attributes before the section are applied to all structs declared in it. Transitions can
+/// use only states of the same state machine and declared events, otherwise the macro fails.
+///
+/// The macro declares the unit struct `Name` with two constructors: `builder()` returns the
+/// `SmBuilder`, and `build()` builds the state machine.
+///
+/// With the header `machine Name, static StaticName;` the macro also generates the statically
+/// dispatched state machine `StaticName` from the same description. Its states are the enum
+/// `StaticNameState`, events are the enum `StaticNameEvent`, transitions are arms of one `match`,
+/// and `process` doesn't allocate. Entry and exit points, actions and completion transitions are
+/// called in the same order as in `Sm`. The nested state machine of the state `A` is
+/// `StaticNameA`, and it is stored in the variant of the state.
+///
+/// ```
+/// umlsm::state_machine! {
+///     machine Door;
+///
+///     #[derive(Debug)]
+///     events { Open, Close, Lock }
+///
+///     #[derive(Debug, PartialEq)]
+///     states {
+///         Closed,
+///         Opened,
+///         final Locked,
+///     }
+///
+///     transitions {
+///         [*] = Closed;
+///         Closed + Open = Opened;
+///         Opened + Close = Closed;
+///         Closed + Lock = Locked;
+///     }
+/// }
+///
+/// let mut sm = Door::build().unwrap();
+/// sm.process(Open).unwrap();
+/// assert_eq!(sm.current_state_concrete(), Some(&Opened));
+/// ```
+///
+/// ```
+/// umlsm::state_machine! {
+///     machine Door, static StaticDoor;
+///     events { Open, Close }
+///     states { Closed, Opened }
+///     transitions {
+///         [*] = Closed;
+///         Closed + Open = Opened;
+///         Opened + Close = Closed;
+///     }
+/// }
+///
+/// let mut door = StaticDoor::new();
+/// door.process(Open.into()).ok().unwrap();
+/// assert!(matches!(door.state(), StaticDoorState::Opened(Opened)));
+/// ```
+///
+/// ```compile_fail
+/// umlsm::state_machine! {
+///     machine Door;
+///     events { Open }
+///     states { Closed }
+///     transitions {
+///         [*] = Closed;
+///         Closed + Open = Opened;
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn state_machine(input: TokenStream) -> TokenStream {
+    let machine = parse_macro_input!(input as state_machine::StateMachine);
+    machine
+        .expand()
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `SmEvent` for the enum, so that it can be used as the event type of `Sm`.
+///
+/// Every variant must contain exactly one event, and events of different variants must have
+/// different types. `From` and `EventOf` are implemented for every event type too, so the
+/// builder restricted to the enum with `SmBuilder::with_events` accepts only transitions
+/// triggered by these events or by internal ones.
+///
+/// ```
+/// use std::any::Any;
+/// use umlsm::state::{InitialPseudoState, SimpleVertex};
+/// use umlsm::transition::ftrans;
+/// use umlsm::{EnterSmEvent, SmBuilder, SmEvent};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Play;
+/// #[derive(Debug, PartialEq)]
+/// struct Stop;
+///
+/// #[derive(Debug, PartialEq, SmEvent)]
+/// enum PlayerEvent {
+///     Play(Play),
+///     Stop(Stop),
+/// }
+///
+/// struct Idle;
+/// struct Playing;
+///
+/// let mut sm = SmBuilder::<dyn Any>::new()
+///     .with_events::<PlayerEvent>()
+///     .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+///     .register_vertex(SimpleVertex::<Playing>::new().to_vertex())
+///     .transition(ftrans(|_: InitialPseudoState, _: EnterSmEvent| Idle))
+///     .transition(ftrans(|_: Idle, _: Play| Playing))
+///     .transition(ftrans(|_: Playing, _: Stop| Idle))
+///     .build()
+///     .unwrap();
+///
+/// sm.process(PlayerEvent::Play(Play)).unwrap();
+/// sm.process(Stop.into()).unwrap();
+/// ```
+///
+/// The transition triggered by the event outside of the enum does not compile:
+///
+/// ```compile_fail
+/// # use std::any::Any;
+/// # use umlsm::state::SimpleVertex;
+/// # use umlsm::transition::ftrans;
+/// # use umlsm::{SmBuilder, SmEvent};
+/// struct Play;
+/// struct Eject;
+///
+/// #[derive(SmEvent)]
+/// enum PlayerEvent {
+///     Play(Play),
+/// }
+///
+/// struct Idle;
+///
+/// SmBuilder::<dyn Any>::new()
+///     .with_events::<PlayerEvent>()
+///     .register_vertex(SimpleVertex::<Idle>::new().to_vertex())
+///     .transition(ftrans(|_: Idle, _: Eject| Idle));
+/// ```
+#[proc_macro_derive(SmEvent)]
+pub fn derive_sm_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    sm_event::expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,110 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::collections::HashSet;
+use syn::{Data, DeriveInput, Fields, Type};
+
+pub fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "`SmEvent` can be derived only for enums",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "`SmEvent` cannot be derived for generic enums",
+        ));
+    }
+
+    let mut variants = vec![];
+    let mut types = HashSet::new();
+    for variant in &data.variants {
+        let ty: &Type = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "every variant must contain exactly one event, e.g. `Play(Play)`",
+                ))
+            }
+        };
+        if !types.insert(quote!(#ty).to_string()) {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "every variant must contain an event of the distinct type",
+            ));
+        }
+        variants.push((&variant.ident, ty));
+    }
+
+    let name = &input.ident;
+    let variant = variants.iter().enumerate().map(|(i, (variant, _))| {
+        quote! { #name::#variant(_) => #i, }
+    });
+    let into_event = variants.iter().map(|(variant, _)| {
+        quote! { #name::#variant(event) => ::umlsm::__private::Box::new(event), }
+    });
+    let from_event = variants.iter().enumerate().map(|(i, (variant, ty))| {
+        quote! {
+            #i => match event.downcast::<#ty>() {
+                ::core::result::Result::Ok(event) => #name::#variant(*event),
+                ::core::result::Result::Err(_) => ::core::panic!(
+                    "The event is not of the type of `{}::{}`.",
+                    ::core::stringify!(#name),
+                    ::core::stringify!(#variant),
+                ),
+            },
+        }
+    });
+    let types = variants.iter().map(|(_, ty)| {
+        quote! { ::umlsm::transition::TypeInfo::of::<#ty>() }
+    });
+    let from = variants.iter().map(|(variant, ty)| {
+        quote! {
+            impl ::core::convert::From<#ty> for #name {
+                fn from(event: #ty) -> Self {
+                    #name::#variant(event)
+                }
+            }
+
+            impl ::umlsm::EventOf<#name> for #ty {}
+        }
+    });
+
+    Ok(quote! {
+        impl ::umlsm::SmEvent for #name {
+            fn variant(&self) -> usize {
+                match *self {
+                    #(#variant)*
+                }
+            }
+
+            fn into_event(self) -> ::umlsm::Event {
+                match self {
+                    #(#into_event)*
+                }
+            }
+
+            fn from_event(variant: usize, event: ::umlsm::Event) -> Self {
+                match variant {
+                    #(#from_event)*
+                    _ => ::core::panic!(
+                        "`{}` has no variant with the index {}.",
+                        ::core::stringify!(#name),
+                        variant,
+                    ),
+                }
+            }
+
+            fn event_types() -> ::umlsm::__private::Vec<::umlsm::transition::TypeInfo> {
+                ::umlsm::__private::vec![#(#types),*]
+            }
+        }
+
+        #(#from)*
+    })
+}
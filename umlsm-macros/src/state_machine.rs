@@ -0,0 +1,473 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use std::collections::HashSet;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{braced, bracketed, parenthesized, Attribute, Expr, Ident, Token, Type, Visibility};
+
+mod static_machine;
+
+/// The whole `state_machine!` block.
+pub struct StateMachine {
+    vis: Visibility,
+    name: Ident,
+    dyn_data: Type,
+    /// Name of the statically dispatched state machine, if it is requested.
+    static_name: Option<Ident>,
+    events: Vec<(Vec<Attribute>, Ident)>,
+    body: Body,
+}
+
+/// States and transitions of the state machine or of the nested state machine.
+struct Body {
+    states: Vec<State>,
+    transitions: Vec<Transition>,
+}
+
+struct State {
+    attrs: Vec<Attribute>,
+    is_final: bool,
+    name: Ident,
+    entry: Option<Expr>,
+    exit: Option<Expr>,
+    nested: Option<Body>,
+}
+
+struct Transition {
+    /// `None` for the initial pseudo-state.
+    source: Option<Ident>,
+    /// `None` for the completion transition and for the transition from the initial
+    /// pseudo-state.
+    event: Option<Ident>,
+    guard: Option<Expr>,
+    target: Ident,
+    action: Option<Expr>,
+}
+
+mod kw {
+    syn::custom_keyword!(machine);
+    syn::custom_keyword!(events);
+    syn::custom_keyword!(states);
+    syn::custom_keyword!(transitions);
+    syn::custom_keyword!(entry);
+    syn::custom_keyword!(exit);
+}
+
+impl Parse for StateMachine {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let vis = input.parse()?;
+        input.parse::<kw::machine>()?;
+        let name = input.parse()?;
+        let dyn_data = match input.parse::<Option<Token![:]>>()? {
+            Some(_) => input.parse()?,
+            None => syn::parse_quote!(dyn ::core::any::Any),
+        };
+        let static_name = match input.parse::<Option<Token![,]>>()? {
+            Some(_) => {
+                input.parse::<Token![static]>()?;
+                Some(input.parse()?)
+            }
+            None => None,
+        };
+        input.parse::<Token![;]>()?;
+
+        let mut events = vec![];
+        let mut body = Body {
+            states: vec![],
+            transitions: vec![],
+        };
+        while !input.is_empty() {
+            let attrs = input.call(Attribute::parse_outer)?;
+            if input.peek(kw::events) {
+                input.parse::<kw::events>()?;
+                let content;
+                braced!(content in input);
+                let names = Punctuated::<Ident, Token![,]>::parse_terminated(&content)?;
+                events.extend(names.into_iter().map(|name| (attrs.clone(), name)));
+            } else {
+                body.parse_section(input, attrs)?;
+            }
+        }
+        Ok(StateMachine {
+            vis,
+            name,
+            dyn_data,
+            static_name,
+            events,
+            body,
+        })
+    }
+}
+
+impl Body {
+    fn parse_section(&mut self, input: ParseStream, attrs: Vec<Attribute>) -> syn::Result<()> {
+        let lookahead = input.lookahead1();
+        if lookahead.peek(kw::states) {
+            input.parse::<kw::states>()?;
+            let content;
+            braced!(content in input);
+            while !content.is_empty() {
+                let mut state: State = content.parse()?;
+                state.attrs.splice(0..0, attrs.iter().cloned());
+                self.states.push(state);
+                if !content.is_empty() {
+                    content.parse::<Token![,]>()?;
+                }
+            }
+        } else if lookahead.peek(kw::transitions) {
+            if !attrs.is_empty() {
+                return Err(syn::Error::new_spanned(
+                    &attrs[0],
+                    "attributes are not allowed on transitions",
+                ));
+            }
+            input.parse::<kw::transitions>()?;
+            let content;
+            braced!(content in input);
+            while !content.is_empty() {
+                self.transitions.push(content.parse()?);
+            }
+        } else {
+            return Err(lookahead.error());
+        }
+        Ok(())
+    }
+}
+
+impl Parse for State {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let is_final = input.parse::<Option<Token![final]>>()?.is_some();
+        let name: Ident = input.parse()?;
+
+        let (mut entry, mut exit) = (None, None);
+        if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            while !content.is_empty() {
+                let lookahead = content.lookahead1();
+                let hook = if lookahead.peek(kw::entry) {
+                    content.parse::<kw::entry>()?;
+                    &mut entry
+                } else if lookahead.peek(kw::exit) {
+                    content.parse::<kw::exit>()?;
+                    &mut exit
+                } else {
+                    return Err(lookahead.error());
+                };
+                content.parse::<Token![=]>()?;
+                *hook = Some(content.parse()?);
+                if !content.is_empty() {
+                    content.parse::<Token![,]>()?;
+                }
+            }
+        }
+
+        let nested = match input.peek(syn::token::Brace) {
+            true => {
+                let content;
+                braced!(content in input);
+                let mut body = Body {
+                    states: vec![],
+                    transitions: vec![],
+                };
+                while !content.is_empty() {
+                    let attrs = content.call(Attribute::parse_outer)?;
+                    body.parse_section(&content, attrs)?;
+                }
+                Some(body)
+            }
+            false => None,
+        };
+        if is_final && nested.is_some() {
+            return Err(syn::Error::new(
+                name.span(),
+                "final state cannot have nested states",
+            ));
+        }
+
+        Ok(State {
+            attrs,
+            is_final,
+            name,
+            entry,
+            exit,
+            nested,
+        })
+    }
+}
+
+impl Parse for Transition {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let source = match input.peek(syn::token::Bracket) {
+            true => {
+                let content;
+                bracketed!(content in input);
+                content.parse::<Token![*]>()?;
+                None
+            }
+            false => Some(input.parse()?),
+        };
+        let event = match input.parse::<Option<Token![+]>>()? {
+            Some(_) => Some(input.parse()?),
+            None => None,
+        };
+        let guard = match input.peek(syn::token::Bracket) {
+            true => {
+                let content;
+                bracketed!(content in input);
+                Some(content.parse()?)
+            }
+            false => None,
+        };
+        input.parse::<Token![=]>()?;
+        let target = input.parse()?;
+        let action = match input.parse::<Option<Token![/]>>()? {
+            Some(_) => Some(input.parse()?),
+            None => None,
+        };
+        input.parse::<Token![;]>()?;
+        Ok(Transition {
+            source,
+            event,
+            guard,
+            target,
+            action,
+        })
+    }
+}
+
+impl StateMachine {
+    pub fn expand(&self) -> syn::Result<TokenStream> {
+        let mut errors = Errors(None);
+        let events: HashSet<_> = self.events.iter().map(|(_, e)| e).collect();
+        let mut declared = HashSet::new();
+        for (_, event) in &self.events {
+            if !declared.insert(event.to_string()) {
+                errors.push(event, format!("event `{}` is declared twice", event));
+            }
+        }
+        let mut declared = HashSet::new();
+        self.body.check(&events, &mut declared, &mut errors);
+        if let Some(error) = errors.0 {
+            return Err(error);
+        }
+
+        let StateMachine {
+            vis,
+            name,
+            dyn_data,
+            ..
+        } = self;
+        let events = self.events.iter().map(|(attrs, name)| {
+            quote! {
+                #(#attrs)*
+                #vis struct #name;
+            }
+        });
+        let mut states = vec![];
+        self.body.declarations(vis, &mut states);
+        let builder = self.body.builder(dyn_data);
+        let static_machine = match &self.static_name {
+            Some(static_name) => self.static_machine(static_name)?,
+            None => quote! {},
+        };
+
+        Ok(quote! {
+            #(#events)*
+            #(#states)*
+            #static_machine
+
+            #vis struct #name;
+
+            impl #name {
+                /// Describes the state machine.
+                #vis fn builder() -> ::umlsm::SmBuilder<#dyn_data> {
+                    #builder
+                }
+
+                /// Builds the state machine.
+                #vis fn build() -> ::core::result::Result<
+                    ::umlsm::Sm<#dyn_data>,
+                    ::umlsm::__private::Vec<::umlsm::BuildError>,
+                > {
+                    Self::builder().build()
+                }
+            }
+        })
+    }
+}
+
+impl StateMachine {
+    /// Generates the statically dispatched state machine, its states and the enum of events.
+    fn static_machine(&self, name: &Ident) -> syn::Result<TokenStream> {
+        let vis = &self.vis;
+        let event = format_ident!("{}Event", name);
+        let trigger = format_ident!("__{}Trigger", name);
+        let variants = self.events.iter().map(|(_, e)| e);
+
+        let mut out = vec![];
+        let names = static_machine::Names {
+            vis,
+            event: &event,
+            trigger: &trigger,
+        };
+        self.body.static_machine(name, &names, false, &mut out)?;
+        Ok(quote! {
+            /// Events of the statically dispatched state machine.
+            #[derive(::umlsm::SmEvent)]
+            #vis enum #event {
+                #(#variants(#variants),)*
+            }
+
+            #[doc(hidden)]
+            #[allow(dead_code)]
+            enum #trigger {
+                Enter(::umlsm::EnterSmEvent),
+                Completion(::umlsm::CompletionEvent),
+                Event(#event),
+            }
+
+            #(#out)*
+        })
+    }
+}
+
+struct Errors(Option<syn::Error>);
+
+impl Errors {
+    fn push(&mut self, ident: &Ident, message: String) {
+        let error = syn::Error::new(ident.span(), message);
+        match &mut self.0 {
+            Some(errors) => errors.combine(error),
+            None => self.0 = Some(error),
+        }
+    }
+}
+
+impl Body {
+    /// Checks that transitions use only states of this state machine and declared events.
+    fn check(&self, events: &HashSet<&Ident>, declared: &mut HashSet<String>, errors: &mut Errors) {
+        for state in &self.states {
+            if !declared.insert(state.name.to_string()) {
+                errors.push(
+                    &state.name,
+                    format!("state `{}` is declared twice", state.name),
+                );
+            }
+            if let Some(nested) = &state.nested {
+                nested.check(events, declared, errors);
+            }
+        }
+        let states: HashSet<_> = self.states.iter().map(|s| &s.name).collect();
+        for transition in &self.transitions {
+            for state in transition.source.iter().chain(Some(&transition.target)) {
+                if !states.contains(state) {
+                    errors.push(state, format!("unknown state `{}`", state));
+                }
+            }
+            if let Some(event) = &transition.event {
+                if transition.source.is_none() {
+                    errors.push(event, "initial transition cannot have an event".to_string());
+                } else if !events.contains(event) {
+                    errors.push(event, format!("unknown event `{}`", event));
+                }
+            }
+        }
+    }
+
+    fn declarations(&self, vis: &Visibility, out: &mut Vec<TokenStream>) {
+        for state in &self.states {
+            let State { attrs, name, .. } = state;
+            out.push(quote! {
+                #(#attrs)*
+                #vis struct #name;
+            });
+            if let Some(nested) = &state.nested {
+                nested.declarations(vis, out);
+            }
+        }
+    }
+
+    fn builder(&self, dyn_data: &Type) -> TokenStream {
+        let vertexes = self.states.iter().map(|state| {
+            let name = &state.name;
+            let mut vertex = quote! { ::umlsm::state::SimpleVertex::<#name>::new() };
+            if let Some(entry) = &state.entry {
+                vertex = quote! { #vertex.with_entry(#entry) };
+            }
+            if let Some(exit) = &state.exit {
+                vertex = quote! { #vertex.with_exit(#exit) };
+            }
+            let vertex = match (&state.nested, state.is_final) {
+                (Some(nested), _) => {
+                    let nested = nested.builder(dyn_data);
+                    quote! { #vertex.to_submachine_vertex(|| { #nested }) }
+                }
+                (None, true) => quote! { #vertex.to_final_vertex() },
+                (None, false) => quote! { #vertex.to_vertex() },
+            };
+            quote! { .register_vertex(#vertex) }
+        });
+        let transitions = self.transitions.iter().map(|t| {
+            let transition = t.expand();
+            quote! { .transition(#transition) }
+        });
+        quote! {
+            ::umlsm::SmBuilder::<#dyn_data>::new()
+                #(#vertexes)*
+                #(#transitions)*
+        }
+    }
+}
+
+impl Transition {
+    fn expand(&self) -> TokenStream {
+        let source = match &self.source {
+            Some(source) => quote! { #source },
+            None => quote! { ::umlsm::state::InitialPseudoState },
+        };
+        let event = match (&self.source, &self.event) {
+            (_, Some(event)) => quote! { #event },
+            (Some(_), None) => quote! { ::umlsm::CompletionEvent },
+            (None, None) => quote! { ::umlsm::EnterSmEvent },
+        };
+        let target = &self.target;
+        let action_var = format_ident!("__action", span = Span::mixed_site());
+        let (action, call) = match &self.action {
+            Some(action) => (
+                quote! {
+                    fn __action<F: Fn(&#source, &#event)>(f: F) -> F {
+                        f
+                    }
+                    let #action_var = __action(#action);
+                },
+                quote! { #action_var(&from, &event); },
+            ),
+            None => (quote! {}, quote! {}),
+        };
+        let transition = quote! {
+            {
+                #action
+                ::umlsm::transition::ftrans(move |from: #source, event: #event| {
+                    #call
+                    let _ = (from, event);
+                    #target
+                })
+            }
+        };
+        match &self.guard {
+            Some(guard) => quote! {
+                {
+                    fn __guard<F: Fn(&#event) -> bool>(f: F) -> F {
+                        f
+                    }
+                    ::umlsm::guard::GuardedTransition::new()
+                        .guard(__guard(#guard))
+                        .transition(#transition)
+                }
+            },
+            None => transition,
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use super::{Body, State, Transition};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Expr, Ident, Visibility};
+
+/// Names shared by all levels of the statically dispatched state machine.
+pub(super) struct Names<'a> {
+    pub vis: &'a Visibility,
+    pub event: &'a Ident,
+    pub trigger: &'a Ident,
+}
+
+impl Body {
+    /// Generates the statically dispatched state machine `name` and state machines of all nested
+    /// states. The state is stored in the enum, and every transition is an arm of one `match`,
+    /// so processing of events doesn't allocate.
+    pub(super) fn static_machine(
+        &self,
+        name: &Ident,
+        names: &Names,
+        nested: bool,
+        out: &mut Vec<TokenStream>,
+    ) -> syn::Result<()> {
+        if !self.transitions.iter().any(|t| t.source.is_none()) {
+            return Err(syn::Error::new(
+                name.span(),
+                "static state machine must have the initial transition `[*] = State;`",
+            ));
+        }
+        let Names {
+            vis,
+            event,
+            trigger,
+        } = names;
+        let state_enum = format_ident!("{}State", name);
+        let nested_name = |state: &State| format_ident!("{}{}", name, state.name);
+
+        let variants = self.states.iter().map(|state| {
+            let data = &state.name;
+            match &state.nested {
+                Some(_) => {
+                    let nested = nested_name(state);
+                    quote! { #data(#data, #nested) }
+                }
+                None => quote! { #data(#data) },
+            }
+        });
+        let arms = self
+            .transitions
+            .iter()
+            .map(|t| self.static_arm(t, &state_enum, names, &nested_name));
+        let sources = self.transitions.iter().map(|t| match &t.source {
+            Some(source) => quote! { #state_enum::#source(..) },
+            None => quote! { #state_enum::__Initial },
+        });
+        let finals = self.states.iter().filter(|s| s.is_final).map(|s| {
+            let data = &s.name;
+            quote! { #state_enum::#data(..) }
+        });
+        let composites: Vec<_> = self
+            .states
+            .iter()
+            .filter(|s| s.nested.is_some())
+            .map(|s| &s.name)
+            .collect();
+
+        // The event is passed to the nested state machine only if there is one.
+        let pass = match composites.is_empty() {
+            true => quote! { ::core::result::Result::Err(err(event)) },
+            false => quote! {
+                match &mut self.state {
+                    #(#state_enum::#composites(_, nested) => nested.process(event)?,)*
+                    _ => return ::core::result::Result::Err(err(event)),
+                }
+                self.complete_step();
+                ::core::result::Result::Ok(())
+            },
+        };
+
+        let exit = match nested {
+            true => {
+                let exits = self.states.iter().map(|state| {
+                    let data = &state.name;
+                    let hook = hook(data, &state.exit, quote! { from });
+                    match &state.nested {
+                        Some(_) => quote! {
+                            #state_enum::#data(from, nested) => {
+                                nested.exit();
+                                #hook
+                            }
+                        },
+                        None => quote! { #state_enum::#data(from) => { #hook } },
+                    }
+                });
+                quote! {
+                    /// Calls exit points of the current state and of all active nested states,
+                    /// from the innermost to the outermost one.
+                    #[allow(unused_variables)]
+                    fn exit(&self) {
+                        match &self.state {
+                            #(#exits)*
+                            #state_enum::__Initial => {}
+                        }
+                    }
+                }
+            }
+            false => quote! {},
+        };
+
+        out.push(quote! {
+            /// States of the statically dispatched state machine.
+            #vis enum #state_enum {
+                #(#variants,)*
+                #[doc(hidden)]
+                __Initial,
+            }
+
+            /// Statically dispatched state machine. Processing of events doesn't allocate.
+            #vis struct #name {
+                state: #state_enum,
+            }
+
+            impl #name {
+                /// Creates the state machine and processes the transition from the initial
+                /// pseudo-state.
+                ///
+                /// Panics if guards of all initial transitions are not satisfied.
+                #vis fn new() -> Self {
+                    let mut sm = #name {
+                        state: #state_enum::__Initial,
+                    };
+                    if sm.step(#trigger::Enter(::umlsm::EnterSmEvent)).is_err() {
+                        ::core::panic!(
+                            "No initial transition of `{}` is satisfied.",
+                            ::core::stringify!(#name)
+                        );
+                    }
+                    sm.complete_step();
+                    sm
+                }
+
+                /// Returns the current state.
+                #vis fn state(&self) -> &#state_enum {
+                    &self.state
+                }
+
+                /// Returns `true` if the state machine is in the final state.
+                #[allow(unreachable_patterns)]
+                #vis fn is_finished(&self) -> bool {
+                    match self.state {
+                        #(#finals => true,)*
+                        _ => false,
+                    }
+                }
+
+                /// Processes the event. If the current state has no transition for it, the event
+                /// is passed to the nested state machine.
+                #vis fn process(
+                    &mut self,
+                    event: #event,
+                ) -> ::core::result::Result<(), ::umlsm::SmError<#event>> {
+                    let event = match self.step(#trigger::Event(event)) {
+                        ::core::result::Result::Ok(()) => {
+                            self.complete_step();
+                            return ::core::result::Result::Ok(());
+                        }
+                        ::core::result::Result::Err(#trigger::Event(event)) => event,
+                        ::core::result::Result::Err(_) => ::core::unreachable!(),
+                    };
+                    let has_transitions = self.has_transitions();
+                    let err = move |event: #event| match has_transitions {
+                        true => ::umlsm::SmError::NoTransitionSatisfyingEvent(event),
+                        false => ::umlsm::SmError::NoTransitionsFromThisVertex(event),
+                    };
+                    #pass
+                }
+
+                #exit
+
+                /// Processes the first satisfied transition from the current state. Returns the
+                /// trigger back if there is none.
+                fn step(&mut self, trigger: #trigger) -> ::core::result::Result<(), #trigger> {
+                    let state = ::core::mem::replace(&mut self.state, #state_enum::__Initial);
+                    match (state, trigger) {
+                        #(#arms)*
+                        (state, trigger) => {
+                            self.state = state;
+                            return ::core::result::Result::Err(trigger);
+                        }
+                    }
+                    ::core::result::Result::Ok(())
+                }
+
+                #[allow(unreachable_patterns)]
+                fn has_transitions(&self) -> bool {
+                    match self.state {
+                        #(#sources => true,)*
+                        _ => false,
+                    }
+                }
+
+                /// Processes `CompletionEvent` if the nested state machine is finished.
+                fn complete_step(&mut self) {
+                    let finished = match &self.state {
+                        #(#state_enum::#composites(_, nested) => nested.is_finished(),)*
+                        _ => false,
+                    };
+                    if finished && self.step(#trigger::Completion(::umlsm::CompletionEvent)).is_ok() {
+                        self.complete_step();
+                    }
+                }
+            }
+
+            impl ::core::default::Default for #name {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+        });
+
+        for state in &self.states {
+            if let Some(body) = &state.nested {
+                body.static_machine(&nested_name(state), names, true, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the arm of `step` for the transition. The order of calls is the same as in
+    /// `Sm`: exit points, the action, then entry points of the target.
+    fn static_arm(
+        &self,
+        transition: &Transition,
+        state_enum: &Ident,
+        names: &Names,
+        nested_name: &dyn Fn(&State) -> Ident,
+    ) -> TokenStream {
+        let Names { event, trigger, .. } = names;
+        let find = |name: &Ident| {
+            self.states
+                .iter()
+                .find(|s| s.name == *name)
+                .expect("It is checked before")
+        };
+
+        let (source_ty, source_pat, exit) = match &transition.source {
+            Some(source) => {
+                let state = find(source);
+                let hook = hook(source, &state.exit, quote! { &from });
+                match &state.nested {
+                    Some(_) => (
+                        quote! { #source },
+                        quote! { #state_enum::#source(from, nested) },
+                        quote! {
+                            nested.exit();
+                            #hook
+                            // The nested state machine is forgotten before the action, as in `Sm`.
+                            let _ = (nested,);
+                        },
+                    ),
+                    None => (
+                        quote! { #source },
+                        quote! { #state_enum::#source(from) },
+                        hook,
+                    ),
+                }
+            }
+            None => (
+                quote! { ::umlsm::state::InitialPseudoState },
+                quote! { #state_enum::__Initial },
+                quote! { let from = ::umlsm::state::InitialPseudoState; },
+            ),
+        };
+        let (event_ty, event_pat) = match (&transition.source, &transition.event) {
+            (_, Some(ev)) => (
+                quote! { #ev },
+                quote! { #trigger::Event(#event::#ev(event)) },
+            ),
+            (Some(_), None) => (
+                quote! { ::umlsm::CompletionEvent },
+                quote! { #trigger::Completion(event) },
+            ),
+            (None, None) => (
+                quote! { ::umlsm::EnterSmEvent },
+                quote! { #trigger::Enter(event) },
+            ),
+        };
+        let guard = transition.guard.as_ref().map(|guard| {
+            quote! {
+                if {
+                    fn __guard<F: Fn(&#event_ty) -> bool>(f: F) -> F {
+                        f
+                    }
+                    __guard(#guard)(&event)
+                }
+            }
+        });
+        let action = transition.action.as_ref().map(|action| {
+            quote! {
+                {
+                    fn __action<F: Fn(&#source_ty, &#event_ty)>(f: F) -> F {
+                        f
+                    }
+                    __action(#action)(&from, &event);
+                }
+            }
+        });
+
+        let target = &transition.target;
+        let state = find(target);
+        let entry = hook(target, &state.entry, quote! { &to });
+        let new_state = match &state.nested {
+            Some(_) => {
+                let nested = nested_name(state);
+                quote! { #state_enum::#target(to, #nested::new()) }
+            }
+            None => quote! { #state_enum::#target(to) },
+        };
+
+        quote! {
+            (#source_pat, #event_pat) #guard => {
+                #exit
+                #action
+                let _ = (from, event);
+                let to = #target;
+                #entry
+                self.state = #new_state;
+            }
+        }
+    }
+}
+
+/// Calls the entry or exit point of the state, if there is one.
+fn hook(state: &Ident, hook: &Option<Expr>, arg: TokenStream) -> TokenStream {
+    match hook {
+        Some(hook) => quote! {
+            {
+                fn __hook<F: Fn(&#state)>(f: F) -> F {
+                    f
+                }
+                __hook(#hook)(#arg);
+            }
+        },
+        None => quote! {},
+    }
+}